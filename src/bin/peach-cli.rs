@@ -0,0 +1,357 @@
+//! Command line interface to the peach-lib client functions.
+//!
+//! Each subcommand maps onto a library function, which makes it possible to
+//! script and debug the PeachCloud microservices without writing a Rust
+//! program. For example:
+//!
+//! ```text
+//! peach-cli wifi scan wlan0
+//! peach-cli dyndns status
+//! peach-cli config get external_domain
+//! ```
+//!
+//! Build it with `cargo build --features cli`.
+
+use std::process;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use peach_lib::error::PeachError;
+use peach_lib::{
+    config_manager, dyndns_client, network_client, oled_client, sbot_client, stats_client,
+};
+
+/// The result of running a subcommand, ready to be printed.
+type Output = Result<String, PeachError>;
+
+/// Serializes a value as pretty-printed JSON for output.
+fn json<T: Serialize>(value: &T) -> Output {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+fn iface_arg() -> Arg<'static, 'static> {
+    Arg::with_name("iface")
+        .help("Network interface, e.g. wlan0")
+        .required(true)
+}
+
+fn wifi_command() -> App<'static, 'static> {
+    SubCommand::with_name("wifi")
+        .about("Query and configure networking via peach-network")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("List available wifi networks")
+                .arg(iface_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Show the status of an interface")
+                .arg(iface_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("state")
+                .about("Show the state of an interface")
+                .arg(iface_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("ip")
+                .about("Show the IP address of an interface")
+                .arg(iface_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("ssid")
+                .about("Show the SSID of the connected network")
+                .arg(iface_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("rssi")
+                .about("Show the signal strength of the connected network")
+                .arg(iface_arg())
+                .arg(
+                    Arg::with_name("percent")
+                        .long("percent")
+                        .help("Show signal quality as a percentage instead of dBm"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("traffic")
+                .about("Show traffic statistics for an interface")
+                .arg(iface_arg()),
+        )
+        .subcommand(SubCommand::with_name("saved").about("List saved wifi networks"))
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Save credentials for a wifi network")
+                .arg(Arg::with_name("ssid").required(true))
+                .arg(Arg::with_name("pass").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("forget")
+                .about("Remove a saved wifi network")
+                .arg(iface_arg())
+                .arg(Arg::with_name("ssid").required(true)),
+        )
+        .subcommand(SubCommand::with_name("ap").about("Activate the access point"))
+        .subcommand(SubCommand::with_name("client").about("Activate the wifi client"))
+        .subcommand(SubCommand::with_name("ping").about("Check peach-network is running"))
+}
+
+fn run_wifi(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("scan", Some(m)) => network_client::available_networks(m.value_of("iface").unwrap()),
+        ("status", Some(m)) => network_client::status(m.value_of("iface").unwrap()),
+        ("state", Some(m)) => network_client::state(m.value_of("iface").unwrap()),
+        ("ip", Some(m)) => network_client::ip(m.value_of("iface").unwrap()),
+        ("ssid", Some(m)) => network_client::ssid(m.value_of("iface").unwrap()),
+        ("rssi", Some(m)) if m.is_present("percent") => {
+            network_client::rssi_percent(m.value_of("iface").unwrap())
+        }
+        ("rssi", Some(m)) => network_client::rssi(m.value_of("iface").unwrap()),
+        ("traffic", Some(m)) => json(&network_client::traffic(m.value_of("iface").unwrap())?),
+        ("saved", _) => network_client::saved_networks(),
+        ("add", Some(m)) => {
+            network_client::add(m.value_of("ssid").unwrap(), m.value_of("pass").unwrap())
+        }
+        ("forget", Some(m)) => {
+            network_client::forget(m.value_of("iface").unwrap(), m.value_of("ssid").unwrap())
+        }
+        ("ap", _) => network_client::activate_ap(),
+        ("client", _) => network_client::activate_client(),
+        ("ping", _) => network_client::ping(),
+        _ => unreachable!(),
+    }
+}
+
+fn dyndns_command() -> App<'static, 'static> {
+    SubCommand::with_name("dyndns")
+        .about("Manage dynamic dns")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("status").about("Show whether dynamic dns is updating"))
+        .subcommand(
+            SubCommand::with_name("available")
+                .about("Check whether a dynamic dns domain is available")
+                .arg(Arg::with_name("domain").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("register")
+                .about("Register a dynamic dns domain")
+                .arg(Arg::with_name("domain").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Update the dynamic dns record with the current IP"),
+        )
+}
+
+fn run_dyndns(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("status", _) => {
+            let online = dyndns_client::is_dns_updater_online()?;
+            let seconds_since_update =
+                dyndns_client::get_num_seconds_since_successful_dns_update()?;
+            json(&serde_json::json!({
+                "online": online,
+                "seconds_since_update": seconds_since_update,
+            }))
+        }
+        ("available", Some(m)) => {
+            Ok(dyndns_client::is_domain_available(m.value_of("domain").unwrap())?.to_string())
+        }
+        ("register", Some(m)) => dyndns_client::register_domain(m.value_of("domain").unwrap()),
+        ("update", _) => Ok(dyndns_client::dyndns_update_ip()?.to_string()),
+        _ => unreachable!(),
+    }
+}
+
+fn config_command() -> App<'static, 'static> {
+    SubCommand::with_name("config")
+        .about("Read the peach config")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Show a single config field")
+                .arg(Arg::with_name("key").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Show the whole config as json, with secrets redacted"),
+        )
+        .subcommand(SubCommand::with_name("domain").about("Show the peachcloud domain"))
+}
+
+fn run_config(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("get", Some(m)) => {
+            let key = m.value_of("key").unwrap();
+            let config = config_manager::as_value()?;
+            match config.get(key) {
+                Some(serde_json::Value::String(value)) => Ok(value.to_string()),
+                Some(value) => json(value),
+                None => exit_with_usage_error(&format!("Unknown config field: {}", key)),
+            }
+        }
+        ("show", _) => config_manager::export_json(true),
+        ("domain", _) => Ok(config_manager::get_peachcloud_domain()?.unwrap_or_default()),
+        _ => unreachable!(),
+    }
+}
+
+fn stats_command() -> App<'static, 'static> {
+    SubCommand::with_name("stats")
+        .about("Query system statistics via peach-stats")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("cpu").about("Show cpu usage percentages"))
+        .subcommand(SubCommand::with_name("disk").about("Show disk usage"))
+        .subcommand(SubCommand::with_name("load").about("Show load averages"))
+        .subcommand(SubCommand::with_name("mem").about("Show memory usage"))
+        .subcommand(SubCommand::with_name("uptime").about("Show uptime in minutes"))
+        .subcommand(SubCommand::with_name("ping").about("Check peach-stats is running"))
+}
+
+fn run_stats(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("cpu", _) => json(&stats_client::cpu_stats_percent()?),
+        ("disk", _) => stats_client::disk_usage(),
+        ("load", _) => json(&stats_client::load_average()?),
+        ("mem", _) => json(&stats_client::mem_stats()?),
+        ("uptime", _) => stats_client::uptime(),
+        ("ping", _) => stats_client::ping(),
+        _ => unreachable!(),
+    }
+}
+
+fn oled_command() -> App<'static, 'static> {
+    SubCommand::with_name("oled")
+        .about("Control the display via peach-oled")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("write")
+                .about("Write text to the display")
+                .arg(Arg::with_name("x").required(true))
+                .arg(Arg::with_name("y").required(true))
+                .arg(Arg::with_name("text").required(true))
+                .arg(
+                    Arg::with_name("font")
+                        .long("font")
+                        .takes_value(true)
+                        .default_value("6x8"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("clear").about("Clear the display"))
+        .subcommand(SubCommand::with_name("flush").about("Flush the display buffer"))
+        .subcommand(SubCommand::with_name("ping").about("Check peach-oled is running"))
+}
+
+fn parse_coordinate(matches: &ArgMatches, name: &str) -> Result<i32, String> {
+    let value = matches.value_of(name).unwrap();
+    value
+        .parse()
+        .map_err(|_| format!("Invalid {} coordinate: {}", name, value))
+}
+
+fn run_oled(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("write", Some(m)) => {
+            let (x, y) = match (parse_coordinate(m, "x"), parse_coordinate(m, "y")) {
+                (Ok(x), Ok(y)) => (x, y),
+                (Err(msg), _) | (_, Err(msg)) => exit_with_usage_error(&msg),
+            };
+            oled_client::write(
+                x,
+                y,
+                m.value_of("text").unwrap(),
+                m.value_of("font").unwrap(),
+            )?;
+            oled_client::flush()?;
+            Ok(String::new())
+        }
+        ("clear", _) => {
+            oled_client::clear()?;
+            oled_client::flush()?;
+            Ok(String::new())
+        }
+        ("flush", _) => oled_client::flush().map(|_| String::new()),
+        ("ping", _) => oled_client::ping().map(|_| String::new()),
+        _ => unreachable!(),
+    }
+}
+
+fn sbot_command() -> App<'static, 'static> {
+    SubCommand::with_name("sbot")
+        .about("Query the sbot via sbotcli")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("whoami").about("Show the ssb id of the sbot"))
+        .subcommand(SubCommand::with_name("online").about("Show whether the sbot is running"))
+        .subcommand(
+            SubCommand::with_name("following")
+                .about("List the feeds followed by a feed (by default the sbot itself)")
+                .arg(Arg::with_name("feed_id").default_value("")),
+        )
+        .subcommand(
+            SubCommand::with_name("post")
+                .about("Publish a post")
+                .arg(Arg::with_name("text").required(true)),
+        )
+}
+
+fn run_sbot(matches: &ArgMatches) -> Output {
+    match matches.subcommand() {
+        ("whoami", _) => sbot_client::whoami(),
+        ("online", _) => Ok(sbot_client::is_sbot_online()?.to_string()),
+        ("following", Some(m)) => {
+            Ok(sbot_client::following(m.value_of("feed_id").unwrap())?.join("\n"))
+        }
+        ("post", Some(m)) => sbot_client::post_returning_key(m.value_of("text").unwrap()),
+        _ => unreachable!(),
+    }
+}
+
+fn exit_with_usage_error(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(2)
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("peach-cli")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Call peach-lib functions from the command line")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(wifi_command())
+        .subcommand(dyndns_command())
+        .subcommand(config_command())
+        .subcommand(stats_command())
+        .subcommand(oled_command())
+        .subcommand(sbot_command())
+        .subcommand(
+            SubCommand::with_name("version")
+                .about("Show the versions of peach-lib and the running services"),
+        )
+        .get_matches();
+
+    let output = match matches.subcommand() {
+        ("wifi", Some(m)) => run_wifi(m),
+        ("dyndns", Some(m)) => run_dyndns(m),
+        ("config", Some(m)) => run_config(m),
+        ("stats", Some(m)) => run_stats(m),
+        ("oled", Some(m)) => run_oled(m),
+        ("sbot", Some(m)) => run_sbot(m),
+        ("version", _) => json(&peach_lib::version()),
+        _ => unreachable!(),
+    };
+
+    match output {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -3,24 +3,68 @@
 //! Different PeachCloud microservices import peach-lib, so that they can share this interface.
 //!
 //! The configuration file is located at: "/var/lib/peachcloud/config.yml"
+//! This can be overridden with the `PEACH_CONFIG_PATH` environment variable.
+//!
+//! When the `PEACH_CONFIG_EXPAND_ENV` environment variable is set to `true`, `${VAR}`
+//! references in string values are replaced with the value of the environment variable
+//! when the config is loaded. Unset variables are left as-is.
+//!
+//! YAML is the default format. When the `toml-config` feature is enabled, a config path
+//! ending in `.toml` is read and written as TOML instead, using the same `PeachConfig` struct.
 
 use fslock::LockFile;
+use log::{debug, warn};
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::dyndns_client::{TSIG_KEY_FILE_MODE, TSIG_KEY_PATH};
 use crate::error::PeachError;
 use crate::error::*;
+use crate::password_utils::{htpasswd_file, HTPASSWD_FILE_MODE};
+
+// log target for this module, e.g. RUST_LOG=peach_lib::config=debug
+const LOG_TARGET: &str = "peach_lib::config";
 
 // main configuration file
 pub const YAML_PATH: &str = "/var/lib/peachcloud/config.yml";
 
-// lock file (used to avoid race conditions during config reading & writing)
+// maximum length (in characters) of the free-text device name
+pub const MAX_DEVICE_NAME_LENGTH: usize = 64;
+
+// name of the feature toggle which mirrors the dyn_enabled field
+pub const DYNDNS_FEATURE: &str = "dyndns";
+
+// file mode for config.yml (owner read/write, group read)
+pub const CONFIG_FILE_MODE: u32 = 0o640;
+
+// default audit log of config changes (overridable with PEACH_CONFIG_AUDIT_LOG)
+pub const AUDIT_LOG_PATH: &str = "/var/lib/peachcloud/config_audit.log";
+
+// lock file (used to avoid race conditions during config reading & writing),
+// overridable with PEACH_CONFIG_LOCK_PATH
 pub const LOCK_FILE_PATH: &str = "/var/lib/peachcloud/config.lock";
 
+// default time to wait for the config lock (overridable with PEACH_CONFIG_LOCK_TIMEOUT_MS)
+pub const LOCK_TIMEOUT_MS: u64 = 10_000;
+
+// time to wait between attempts to acquire the config lock
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
 // we make use of Serde default values in order to make PeachCloud
 // robust and keep running even with a not fully complete config.yml
 // main type which represents all peachcloud configurations
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PeachConfig {
     #[serde(default)]
     pub external_domain: String,
@@ -34,51 +78,349 @@ pub struct PeachConfig {
     pub dyn_enabled: bool,
     #[serde(default)] // default is empty vector
     pub ssb_admin_ids: Vec<String>,
+    #[serde(default)]
+    pub tor_address: String,
+    #[serde(default)]
+    pub device_name: String,
+    #[serde(default)] // default is false
+    pub setup_complete: bool,
+    #[serde(default)]
+    pub admin_email: String,
+    #[serde(default)]
+    pub dyn_update_webhook: String,
+    #[serde(default)] // default is false
+    pub maintenance_mode: bool,
+    // kept as the last field: toml requires tables to follow plain values
+    #[serde(default)] // default is empty map
+    pub features: HashMap<String, bool>,
 }
 
-// helper functions for serializing and deserializing PeachConfig from disc
-fn save_peach_config(peach_config: PeachConfig) -> Result<PeachConfig, PeachError> {
-    // use a file lock to avoid race conditions while saving config
-    let mut lock = LockFile::open(LOCK_FILE_PATH)?;
-    lock.lock()?;
+// in-memory copy of the config, used by load_peach_config_cached to avoid
+// re-reading and parsing the yaml file on every call. it is cleared whenever
+// the config is saved through this module.
+static CONFIG_CACHE: RwLock<Option<PeachConfig>> = RwLock::new(None);
 
-    let yaml_str = serde_yaml::to_string(&peach_config)?;
+// helper function which clears the cached config so the next cached read reloads it from disk
+fn invalidate_config_cache() {
+    let mut cache = match CONFIG_CACHE.write() {
+        Ok(cache) => cache,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    *cache = None;
+}
 
-    fs::write(YAML_PATH, yaml_str).context(WriteConfigError {
-        file: YAML_PATH.to_string(),
-    })?;
+// returns the path of the main configuration file
+pub fn config_path() -> String {
+    env::var("PEACH_CONFIG_PATH").unwrap_or_else(|_| YAML_PATH.to_string())
+}
+
+// returns the path of the config lock file
+fn lock_file_path() -> String {
+    env::var("PEACH_CONFIG_LOCK_PATH").unwrap_or_else(|_| LOCK_FILE_PATH.to_string())
+}
+
+// helper function which returns true if the config file should be read and written as toml
+fn is_toml_path(path: &str) -> bool {
+    path.ends_with(".toml")
+}
+
+// helper function which serializes the config in the format selected by the file extension
+fn serialize_config(peach_config: &PeachConfig, path: &str) -> Result<String, PeachError> {
+    if is_toml_path(path) {
+        #[cfg(feature = "toml-config")]
+        return toml::to_string(peach_config).map_err(|err| PeachError::TomlError {
+            msg: err.to_string(),
+        });
+        #[cfg(not(feature = "toml-config"))]
+        return Err(PeachError::UnsupportedConfigFormat {
+            file: path.to_string(),
+        });
+    }
+    let yaml_str = serde_yaml::to_string(peach_config)?;
+    Ok(yaml_str)
+}
+
+// helper function which deserializes the config in the format selected by the file extension
+fn deserialize_config(contents: &str, path: &str) -> Result<PeachConfig, PeachError> {
+    if is_toml_path(path) {
+        #[cfg(feature = "toml-config")]
+        return toml::from_str(contents).map_err(|err| PeachError::TomlError {
+            msg: err.to_string(),
+        });
+        #[cfg(not(feature = "toml-config"))]
+        return Err(PeachError::UnsupportedConfigFormat {
+            file: path.to_string(),
+        });
+    }
+    let peach_config = serde_yaml::from_str(contents)?;
+    Ok(peach_config)
+}
+
+// helper function which acquires the config file lock, retrying until the timeout elapses.
+// this avoids blocking forever if a crashed process left the lock held.
+fn acquire_config_lock() -> Result<LockFile, PeachError> {
+    let timeout_ms = env::var("PEACH_CONFIG_LOCK_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(LOCK_TIMEOUT_MS);
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let lock_path = lock_file_path();
+    let mut lock = LockFile::open(lock_path.as_str())?;
+    loop {
+        if lock.try_lock()? {
+            return Ok(lock);
+        }
+        if Instant::now() >= deadline {
+            warn!(target: LOG_TARGET,
+                "Timed out after {}ms waiting for config lock: {}",
+                timeout_ms, lock_path
+            );
+            return Err(PeachError::ConfigLockTimeout { path: lock_path });
+        }
+        debug!(target: LOG_TARGET, "Config lock is held by another process: {}", lock_path);
+        thread::sleep(LOCK_RETRY_INTERVAL);
+    }
+}
+
+// helper function which writes the config to disk. callers must hold the config file lock.
+fn write_peach_config(peach_config: &PeachConfig) -> Result<(), PeachError> {
+    let path = config_path();
+    let config_str = serialize_config(peach_config, &path)?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(CONFIG_FILE_MODE)
+        .open(&path)
+        .context(WriteConfigError { file: path.clone() })?;
+    file.write_all(config_str.as_bytes())
+        .context(WriteConfigError { file: path.clone() })?;
+    // the mode given to open only applies when the file is created
+    fs::set_permissions(&path, fs::Permissions::from_mode(CONFIG_FILE_MODE))
+        .context(WriteConfigError { file: path.clone() })?;
+
+    Ok(())
+}
+
+// helper function which loads the config, applies a fallible change to it and saves it,
+// all under a single file lock. if the change returns an error nothing is written.
+fn try_update<F>(f: F) -> Result<PeachConfig, PeachError>
+where
+    F: FnOnce(&mut PeachConfig) -> Result<(), PeachError>,
+{
+    // use a file lock to avoid race conditions while saving config
+    let mut lock = acquire_config_lock()?;
+
+    // keep the previous config so the audit log can record what changed
+    // the raw config is used so that ${VAR} references are preserved when saving
+    let result = load_raw_peach_config().and_then(|previous_config| {
+        let mut peach_config = previous_config.clone();
+        f(&mut peach_config)?;
+        write_peach_config(&peach_config)?;
+        Ok((previous_config, peach_config))
+    });
 
     // unlock file lock
     lock.unlock()?;
 
+    let (previous_config, peach_config) = result?;
+
+    invalidate_config_cache();
+
+    // logging is best-effort: a failure to write the audit log never fails the save
+    if let Err(err) = log_config_changes(&previous_config, &peach_config) {
+        warn!(target: LOG_TARGET, "Failed to write config audit log: {}", err);
+    }
+
     // return peach_config
     Ok(peach_config)
 }
 
+// loads the config, applies the given change to it and saves it,
+// all under a single file lock, so that several fields can be set at once
+// without a race window between separate load/save cycles.
+pub fn update<F>(f: F) -> Result<PeachConfig, PeachError>
+where
+    F: FnOnce(&mut PeachConfig),
+{
+    try_update(|peach_config| {
+        f(peach_config);
+        Ok(())
+    })
+}
+
+// helper function which replaces the whole config
+fn save_peach_config(peach_config: PeachConfig) -> Result<PeachConfig, PeachError> {
+    update(|current_config| *current_config = peach_config)
+}
+
+// a single field which differs between two configs, with its old and new values
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+// returns the fields which differ between two configs, with their old and new values.
+// fields are compared via their serde representation so new fields are covered automatically,
+// which also makes this useful for showing which defaults were filled in after an upgrade.
+pub fn diff(a: &PeachConfig, b: &PeachConfig) -> Vec<FieldChange> {
+    let a_value = serde_json::to_value(a).unwrap_or_default();
+    let b_value = serde_json::to_value(b).unwrap_or_default();
+    let mut changes = Vec::new();
+    if let (Some(a_map), Some(b_map)) = (a_value.as_object(), b_value.as_object()) {
+        for (field, new_value) in b_map {
+            let old_value = a_map.get(field).cloned().unwrap_or_default();
+            if old_value != *new_value {
+                changes.push(FieldChange {
+                    field: field.to_string(),
+                    old: old_value,
+                    new: new_value.clone(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+// helper function which appends a timestamped line listing the changed fields to the audit log
+fn log_config_changes(old: &PeachConfig, new: &PeachConfig) -> Result<(), PeachError> {
+    let fields: Vec<String> = diff(old, new)
+        .into_iter()
+        .map(|change| change.field)
+        .collect();
+    if fields.is_empty() {
+        return Ok(());
+    }
+    let audit_log_path =
+        env::var("PEACH_CONFIG_AUDIT_LOG").unwrap_or_else(|_| AUDIT_LOG_PATH.to_string());
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&audit_log_path)
+        .context(WriteConfigError {
+            file: audit_log_path.to_string(),
+        })?;
+    let now_timestamp = chrono::offset::Utc::now().to_rfc3339();
+    writeln!(file, "{} changed: {}", now_timestamp, fields.join(", ")).context(
+        WriteConfigError {
+            file: audit_log_path.to_string(),
+        },
+    )?;
+    Ok(())
+}
+
+// helper function which replaces ${VAR} references in a string with the value of the
+// environment variable, leaving references to unset variables untouched
+fn expand_env_vars(re: &Regex, value: &str) -> String {
+    re.replace_all(value, |caps: &Captures| match env::var(&caps[1]) {
+        Ok(var_value) => var_value,
+        Err(_) => {
+            warn!(target: LOG_TARGET, "Config references unset environment variable: {}", &caps[1]);
+            caps[0].to_string()
+        }
+    })
+    .to_string()
+}
+
+// helper function which expands ${VAR} references in every string in a json value
+fn expand_env_vars_in_value(re: &Regex, value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(string) => *string = expand_env_vars(re, string),
+        serde_json::Value::Array(values) => {
+            for value in values {
+                expand_env_vars_in_value(re, value);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                expand_env_vars_in_value(re, value);
+            }
+        }
+        _ => (),
+    }
+}
+
+// helper function which returns true if ${VAR} expansion has been opted into
+fn is_env_expansion_enabled() -> bool {
+    env::var("PEACH_CONFIG_EXPAND_ENV")
+        .map(|value| value == "true")
+        .unwrap_or(false)
+}
+
+// loads the config, expanding ${VAR} references in string values if PEACH_CONFIG_EXPAND_ENV
+// is set to true. expanded values are never written back to disk by this module.
 pub fn load_peach_config() -> Result<PeachConfig, PeachError> {
-    let peach_config_exists = std::path::Path::new(YAML_PATH).exists();
+    let peach_config = load_raw_peach_config()?;
+    if !is_env_expansion_enabled() {
+        return Ok(peach_config);
+    }
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}")?;
+    let mut value = serde_json::to_value(peach_config)?;
+    expand_env_vars_in_value(&re, &mut value);
+    let peach_config = serde_json::from_value(value)?;
+    Ok(peach_config)
+}
 
-    let peach_config: PeachConfig;
+// helper function which loads the config exactly as it is stored on disk
+fn load_raw_peach_config() -> Result<PeachConfig, PeachError> {
+    let path = config_path();
+    let peach_config_exists = std::path::Path::new(&path).exists();
 
     // if this is the first time loading peach_config, we can create a default here
-    if !peach_config_exists {
-        peach_config = PeachConfig {
+    let peach_config = if !peach_config_exists {
+        PeachConfig {
             external_domain: "".to_string(),
             dyn_domain: "".to_string(),
             dyn_dns_server_address: "".to_string(),
             dyn_tsig_key_path: "".to_string(),
             dyn_enabled: false,
             ssb_admin_ids: Vec::new(),
-        };
+            tor_address: "".to_string(),
+            device_name: "".to_string(),
+            setup_complete: false,
+            admin_email: "".to_string(),
+            dyn_update_webhook: "".to_string(),
+            maintenance_mode: false,
+            features: HashMap::new(),
+        }
     }
     // otherwise we load peach config from disk
     else {
-        let contents = fs::read_to_string(YAML_PATH).context(ReadConfigError {
-            file: YAML_PATH.to_string(),
-        })?;
-        peach_config = serde_yaml::from_str(&contents)?;
+        let contents = fs::read_to_string(&path).context(ReadConfigError { file: path.clone() })?;
+        deserialize_config(&contents, &path)?
+    };
+
+    Ok(peach_config)
+}
+
+// returns the config from the in-memory cache, reading it from disk only on the first call
+// or after the cache has been invalidated. callers which need to see edits made outside
+// of peach-lib should use load_peach_config, or call reload_config first.
+pub fn load_peach_config_cached() -> Result<PeachConfig, PeachError> {
+    {
+        let cache = match CONFIG_CACHE.read() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(peach_config) = cache.as_ref() {
+            return Ok(peach_config.clone());
+        }
     }
+    reload_config()
+}
 
+// forces the cached config to be refreshed from disk,
+// e.g. when config.yml has been edited externally
+pub fn reload_config() -> Result<PeachConfig, PeachError> {
+    let peach_config = load_peach_config()?;
+    let mut cache = match CONFIG_CACHE.write() {
+        Ok(cache) => cache,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    *cache = Some(peach_config.clone());
     Ok(peach_config)
 }
 
@@ -89,55 +431,529 @@ pub fn set_peach_dyndns_config(
     dyn_tsig_key_path: &str,
     dyn_enabled: bool,
 ) -> Result<PeachConfig, PeachError> {
-    let mut peach_config = load_peach_config()?;
-    peach_config.dyn_domain = dyn_domain.to_string();
-    peach_config.dyn_dns_server_address = dyn_dns_server_address.to_string();
-    peach_config.dyn_tsig_key_path = dyn_tsig_key_path.to_string();
-    peach_config.dyn_enabled = dyn_enabled;
-    save_peach_config(peach_config)
+    ensure_not_in_maintenance_mode()?;
+    update(|peach_config| {
+        peach_config.dyn_domain = dyn_domain.to_string();
+        peach_config.dyn_dns_server_address = dyn_dns_server_address.to_string();
+        peach_config.dyn_tsig_key_path = dyn_tsig_key_path.to_string();
+        peach_config.dyn_enabled = dyn_enabled;
+        peach_config
+            .features
+            .insert(DYNDNS_FEATURE.to_string(), dyn_enabled);
+    })
 }
 
 pub fn set_external_domain(new_external_domain: &str) -> Result<PeachConfig, PeachError> {
-    let mut peach_config = load_peach_config()?;
-    peach_config.external_domain = new_external_domain.to_string();
-    save_peach_config(peach_config)
+    update(|peach_config| peach_config.external_domain = new_external_domain.to_string())
 }
 
-pub fn get_peachcloud_domain() -> Result<Option<String>, PeachError> {
-    let peach_config = load_peach_config()?;
+// clears the external domain. get_peachcloud_domain then falls back to the dyndns
+// domain, if one is configured.
+pub fn clear_external_domain() -> Result<PeachConfig, PeachError> {
+    update(|peach_config| peach_config.external_domain = "".to_string())
+}
+
+// clears the dyndns domain, server address and tsig key path and disables dyndns.
+// the tsig key file itself is left on disk.
+pub fn clear_dyndns_config() -> Result<PeachConfig, PeachError> {
+    set_peach_dyndns_config("", "", "", false)
+}
+
+// returns the external domain of the given config if one is set, otherwise the
+// dyndns domain, otherwise None
+pub fn peachcloud_domain(peach_config: &PeachConfig) -> Option<String> {
     if !peach_config.external_domain.is_empty() {
-        Ok(Some(peach_config.external_domain))
+        Some(peach_config.external_domain.clone())
     } else if !peach_config.dyn_domain.is_empty() {
-        Ok(Some(peach_config.dyn_domain))
+        Some(peach_config.dyn_domain.clone())
+    } else {
+        None
+    }
+}
+
+// returns the best address for reaching the device with the given config: its
+// domain (see peachcloud_domain), falling back to the onion address
+pub fn peachcloud_address(peach_config: &PeachConfig) -> Option<String> {
+    peachcloud_domain(peach_config).or_else(|| {
+        if peach_config.tor_address.is_empty() {
+            None
+        } else {
+            Some(peach_config.tor_address.clone())
+        }
+    })
+}
+
+// returns the external domain if one is set, otherwise the dyndns domain,
+// otherwise None
+pub fn get_peachcloud_domain() -> Result<Option<String>, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(peachcloud_domain(&peach_config))
+}
+
+// helper function which checks that a tor address is a v3 onion address
+// (56 base32 characters followed by .onion). empty clears the tor address.
+fn validate_tor_address(tor_address: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^[a-z2-7]{56}\.onion$")?;
+    if !tor_address.is_empty() && !re.is_match(tor_address) {
+        return Err(PeachError::InvalidOnionAddress {
+            address: tor_address.to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub fn set_tor_address(new_tor_address: &str) -> Result<PeachConfig, PeachError> {
+    validate_tor_address(new_tor_address)?;
+    update(|peach_config| peach_config.tor_address = new_tor_address.to_string())
+}
+
+pub fn get_tor_address() -> Result<Option<String>, PeachError> {
+    let peach_config = load_peach_config()?;
+    if !peach_config.tor_address.is_empty() {
+        Ok(Some(peach_config.tor_address))
     } else {
         Ok(None)
     }
 }
 
+// returns the best address for reaching this device, preferring a clearnet
+// domain and falling back to the onion address when no domain is configured
+pub fn get_peachcloud_address() -> Result<Option<String>, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(peachcloud_address(&peach_config))
+}
+
+// the device name is a free-text display name (e.g. "Alice's PeachCloud"),
+// independent of the network hostname and domain
+// helper function which checks that a device name is not too long
+fn validate_device_name(device_name: &str) -> Result<(), PeachError> {
+    if device_name.chars().count() > MAX_DEVICE_NAME_LENGTH {
+        return Err(PeachError::DeviceNameTooLong {
+            max_length: MAX_DEVICE_NAME_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+pub fn set_device_name(new_device_name: &str) -> Result<PeachConfig, PeachError> {
+    validate_device_name(new_device_name)?;
+    update(|peach_config| peach_config.device_name = new_device_name.to_string())
+}
+
+pub fn get_device_name() -> Result<String, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(peach_config.device_name)
+}
+
+// records that the initial setup (password, wifi, identity) has been completed,
+// so that the web ui no longer forces the setup wizard
+pub fn mark_setup_complete() -> Result<PeachConfig, PeachError> {
+    update(|peach_config| peach_config.setup_complete = true)
+}
+
+pub fn is_setup_complete() -> Result<bool, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(peach_config.setup_complete)
+}
+
 pub fn set_dyndns_enabled_value(enabled_value: bool) -> Result<PeachConfig, PeachError> {
-    let mut peach_config = load_peach_config()?;
-    peach_config.dyn_enabled = enabled_value;
-    save_peach_config(peach_config)
+    ensure_not_in_maintenance_mode()?;
+    update(|peach_config| {
+        peach_config.dyn_enabled = enabled_value;
+        peach_config
+            .features
+            .insert(DYNDNS_FEATURE.to_string(), enabled_value);
+    })
+}
+
+// generic on/off toggles, so that new features don't require a schema change.
+// the "dyndns" toggle is kept in sync with the dedicated dyn_enabled field.
+pub fn is_feature_enabled(key: &str) -> Result<bool, PeachError> {
+    let peach_config = load_peach_config()?;
+    if key == DYNDNS_FEATURE {
+        return Ok(peach_config.dyn_enabled);
+    }
+    // features which have never been set are disabled
+    Ok(peach_config.features.get(key).copied().unwrap_or(false))
+}
+
+pub fn set_feature(key: &str, enabled: bool) -> Result<PeachConfig, PeachError> {
+    // the dyndns feature is dyn_enabled, which is guarded by maintenance mode
+    if key == DYNDNS_FEATURE {
+        return set_dyndns_enabled_value(enabled);
+    }
+    update(|peach_config| {
+        peach_config.features.insert(key.to_string(), enabled);
+    })
+}
+
+// the admin email is used as a fallback channel for password resets
+// when the temporary password cannot be sent as an ssb private message
+// helper function which checks that an email address has a single @ with a domain after it,
+// and no whitespace (which would allow extra headers to be added to the emails sent to it).
+// empty clears the admin email.
+fn validate_email_address(address: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$")?;
+    if !address.is_empty() && !re.is_match(address) {
+        return Err(PeachError::InvalidEmailAddress {
+            address: address.to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub fn set_admin_email(new_admin_email: &str) -> Result<PeachConfig, PeachError> {
+    validate_email_address(new_admin_email)?;
+    update(|peach_config| peach_config.admin_email = new_admin_email.to_string())
+}
+
+pub fn get_admin_email() -> Result<Option<String>, PeachError> {
+    let peach_config = load_peach_config()?;
+    if !peach_config.admin_email.is_empty() {
+        Ok(Some(peach_config.admin_email))
+    } else {
+        Ok(None)
+    }
+}
+
+// helper function which checks that a webhook is an http(s) url, since it is passed to curl
+// (which would otherwise also accept e.g. file:// urls). empty clears the webhook.
+fn validate_webhook_url(webhook: &str) -> Result<(), PeachError> {
+    let lowercase = webhook.to_lowercase();
+    let is_http = lowercase.starts_with("http://") || lowercase.starts_with("https://");
+    if !webhook.is_empty() && (!is_http || webhook.chars().any(|c| c.is_whitespace())) {
+        return Err(PeachError::InvalidWebhookUrl {
+            url: webhook.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// the webhook url is sent a POST request after each successful dyndns update,
+// so that integrators can trigger downstream actions when the ip changes
+pub fn set_dyn_update_webhook(new_webhook: &str) -> Result<PeachConfig, PeachError> {
+    validate_webhook_url(new_webhook)?;
+    update(|peach_config| peach_config.dyn_update_webhook = new_webhook.to_string())
+}
+
+pub fn get_dyn_update_webhook() -> Result<Option<String>, PeachError> {
+    let peach_config = load_peach_config()?;
+    if !peach_config.dyn_update_webhook.is_empty() {
+        Ok(Some(peach_config.dyn_update_webhook))
+    } else {
+        Ok(None)
+    }
+}
+
+// maintenance mode makes the device refuse state-changing operations (saving wifi
+// credentials, setting the password, enabling dyndns) while it is being upgraded.
+// the config itself can still be changed, so that maintenance mode can be turned off.
+pub fn set_maintenance_mode(enabled: bool) -> Result<PeachConfig, PeachError> {
+    update(|peach_config| peach_config.maintenance_mode = enabled)
+}
+
+pub fn is_maintenance_mode() -> Result<bool, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(peach_config.maintenance_mode)
+}
+
+// returns PeachError::MaintenanceMode if maintenance mode is on.
+// called at the start of each state-changing operation.
+pub fn ensure_not_in_maintenance_mode() -> Result<(), PeachError> {
+    if is_maintenance_mode()? {
+        return Err(PeachError::MaintenanceMode);
+    }
+    Ok(())
 }
 
 pub fn add_ssb_admin_id(ssb_id: &str) -> Result<PeachConfig, PeachError> {
-    let mut peach_config = load_peach_config()?;
-    peach_config.ssb_admin_ids.push(ssb_id.to_string());
-    save_peach_config(peach_config)
+    update(|peach_config| peach_config.ssb_admin_ids.push(ssb_id.to_string()))
 }
 
 pub fn delete_ssb_admin_id(ssb_id: &str) -> Result<PeachConfig, PeachError> {
+    try_update(|peach_config| {
+        let index_result = peach_config.ssb_admin_ids.iter().position(|x| *x == ssb_id);
+        match index_result {
+            Some(index) => {
+                peach_config.ssb_admin_ids.remove(index);
+                Ok(())
+            }
+            None => Err(PeachError::SsbAdminIdNotFound {
+                id: ssb_id.to_string(),
+            }),
+        }
+    })
+}
+
+// checks a whole config with the same rules as the setters for the individual fields,
+// for configs which replace the current one wholesale (e.g. on import). fields which
+// are empty have not been set, which the setters also allow.
+pub fn validate(peach_config: &PeachConfig) -> Result<(), PeachError> {
+    validate_tor_address(&peach_config.tor_address)?;
+    validate_device_name(&peach_config.device_name)?;
+    validate_webhook_url(&peach_config.dyn_update_webhook)?;
+    validate_email_address(&peach_config.admin_email)?;
+    Ok(())
+}
+
+// interfaces for exporting and importing the whole config as json
+
+// returns the full PeachCloud configuration as a json value, so that callers
+// (e.g. settings templates) can iterate over the fields without knowing the
+// shape of PeachConfig. newly added fields are included automatically.
+pub fn as_value() -> Result<serde_json::Value, PeachError> {
+    let peach_config = load_peach_config()?;
+    let value = serde_json::to_value(peach_config)?;
+    Ok(value)
+}
+
+// returns the full PeachCloud configuration serialized as json.
+// if redacted is true, the tsig key path is blanked so the output can be shared for support.
+pub fn export_json(redacted: bool) -> Result<String, PeachError> {
     let mut peach_config = load_peach_config()?;
-    let mut ssb_admin_ids = peach_config.ssb_admin_ids;
-    let index_result = ssb_admin_ids.iter().position(|x| *x == ssb_id);
-    match index_result {
-        Some(index) => {
-            ssb_admin_ids.remove(index);
-            peach_config.ssb_admin_ids = ssb_admin_ids;
-            save_peach_config(peach_config)
+    if redacted {
+        peach_config.dyn_tsig_key_path = "".to_string();
+    }
+    let json_str = serde_json::to_string_pretty(&peach_config)?;
+    Ok(json_str)
+}
+
+// replaces the PeachCloud configuration with the one contained in the given json.
+// the json is fully parsed and checked with validate before anything is written,
+// so an invalid document leaves the existing configuration untouched.
+pub fn import_json(json: &str) -> Result<PeachConfig, PeachError> {
+    let peach_config: PeachConfig = serde_json::from_str(json)?;
+    validate(&peach_config)?;
+    save_peach_config(peach_config)
+}
+
+// interfaces for backing up and restoring all peachcloud state as a single tar archive
+
+// names of the files in a state archive. the ssb admin ids are part of config.yml.
+const STATE_CONFIG_FILE: &str = "config.yml";
+const STATE_TSIG_KEY_FILE: &str = "tsig.key";
+const STATE_HTPASSWD_FILE: &str = "htpasswd";
+
+// file mode for state archives, which contain secrets (owner read/write only)
+pub const STATE_ARCHIVE_FILE_MODE: u32 = 0o600;
+
+// helper function which returns the path of the tsig key for the given config
+fn tsig_key_path(peach_config: &PeachConfig) -> String {
+    if peach_config.dyn_tsig_key_path.is_empty() {
+        TSIG_KEY_PATH.to_string()
+    } else {
+        peach_config.dyn_tsig_key_path.clone()
+    }
+}
+
+// helper function which creates an empty, private directory for staging the files of an archive
+fn create_staging_dir() -> Result<PathBuf, PeachError> {
+    let dir = env::temp_dir().join(format!(
+        "peach-state-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_subsec_nanos()
+    ));
+    fs::create_dir(&dir).context(StdIoError {
+        msg: format!("Failed to create staging directory {}", dir.display()),
+    })?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).context(StdIoError {
+        msg: format!("Failed to set permissions of {}", dir.display()),
+    })?;
+    Ok(dir)
+}
+
+// helper function which runs tar with the given arguments, returning its stdout
+fn run_tar(args: &[&str]) -> Result<String, PeachError> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .context(StdIoError {
+            msg: "tar is not installed",
+        })?;
+    if !output.status.success() {
+        return Err(PeachError::StateArchiveError {
+            msg: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// helper function which writes a file containing secrets, creating its directory if needed
+// and setting its mode even if the file already existed
+fn write_secret_file(path: &str, contents: &[u8], mode: u32) -> Result<(), PeachError> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).context(StdIoError {
+            msg: format!("Failed to create directory {}", parent.display()),
+        })?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .context(StdIoError {
+            msg: format!("Failed to open {}", path),
+        })?;
+    file.write_all(contents).context(StdIoError {
+        msg: format!("Failed to write {}", path),
+    })?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).context(StdIoError {
+        msg: format!("Failed to set permissions of {}", path),
+    })?;
+    Ok(())
+}
+
+// helper function which copies the state files into the staging directory
+// and returns the names of the files which were staged
+fn stage_state(dir: &Path) -> Result<Vec<&'static str>, PeachError> {
+    let peach_config = load_raw_peach_config()?;
+    // the config is always archived as yaml, whatever format it is stored in
+    let config_str = serialize_config(&peach_config, STATE_CONFIG_FILE)?;
+    let mut staged = vec![STATE_CONFIG_FILE];
+    let mut secrets = vec![(STATE_CONFIG_FILE, config_str.into_bytes())];
+    // the tsig key and htpasswd are only present once dyndns and the password have been set up
+    for (name, path) in &[
+        (STATE_TSIG_KEY_FILE, tsig_key_path(&peach_config)),
+        (STATE_HTPASSWD_FILE, htpasswd_file()),
+    ] {
+        if Path::new(path).exists() {
+            let contents = fs::read(path).context(StdIoError {
+                msg: format!("Failed to read {}", path),
+            })?;
+            staged.push(name);
+            secrets.push((name, contents));
         }
-        None => Err(PeachError::SsbAdminIdNotFound {
-            id: ssb_id.to_string(),
-        }),
     }
+    for (name, contents) in secrets {
+        let staged_path = dir.join(name);
+        write_secret_file(&staged_path.to_string_lossy(), &contents, 0o600)?;
+    }
+    Ok(staged)
+}
+
+// writes config.yml, the dyndns tsig key and the htpasswd file (with the ssb admin ids
+// contained in config.yml) to a gzipped tar archive at the given path, e.g. for migrating
+// to new hardware. the archive contains secrets, so it is only readable by its owner.
+pub fn export_state(path: &str) -> Result<(), PeachError> {
+    let dir = create_staging_dir()?;
+    let result = stage_state(&dir).and_then(|staged| {
+        let dir_str = dir.to_string_lossy();
+        let mut args = vec!["-czf", path, "-C", &dir_str];
+        args.extend(staged);
+        run_tar(&args)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(STATE_ARCHIVE_FILE_MODE)).context(
+            StdIoError {
+                msg: format!("Failed to set permissions of {}", path),
+            },
+        )
+    });
+    // the staged copies of the secrets are removed whether or not the export succeeded
+    if let Err(err) = fs::remove_dir_all(&dir) {
+        warn!(target: LOG_TARGET, "Failed to remove staging directory {}: {}", dir.display(), err);
+    }
+    result
+}
+
+// helper function which checks that an archive contains config.yml and otherwise only
+// known state files, so that nothing unexpected is extracted
+fn validate_state_archive(path: &str) -> Result<(), PeachError> {
+    let listing = run_tar(&["-tzf", path])?;
+    let names: Vec<&str> = listing
+        .lines()
+        .map(|name| name.trim_start_matches("./"))
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in &names {
+        if ![STATE_CONFIG_FILE, STATE_TSIG_KEY_FILE, STATE_HTPASSWD_FILE].contains(name) {
+            return Err(PeachError::StateArchiveError {
+                msg: format!("unexpected file {}", name),
+            });
+        }
+    }
+    if !names.contains(&STATE_CONFIG_FILE) {
+        return Err(PeachError::StateArchiveError {
+            msg: format!("missing {}", STATE_CONFIG_FILE),
+        });
+    }
+    Ok(())
+}
+
+// helper function which reads an extracted state file, returning None if the archive did
+// not contain it. members which are not regular files (e.g. a symlink to a file elsewhere
+// on the device) are rejected rather than followed.
+fn read_state_file(dir: &Path, name: &str) -> Result<Option<Vec<u8>>, PeachError> {
+    let path = dir.join(name);
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).context(StdIoError {
+                msg: format!("Failed to read archived {}", name),
+            })
+        }
+    };
+    if !metadata.is_file() {
+        return Err(PeachError::StateArchiveError {
+            msg: format!("{} is not a regular file", name),
+        });
+    }
+    let contents = fs::read(&path).context(StdIoError {
+        msg: format!("Failed to read archived {}", name),
+    })?;
+    Ok(Some(contents))
+}
+
+// helper function which restores the state files from the staging directory
+fn restore_state(dir: &Path) -> Result<PeachConfig, PeachError> {
+    let contents =
+        read_state_file(dir, STATE_CONFIG_FILE)?.ok_or_else(|| PeachError::StateArchiveError {
+            msg: format!("missing {}", STATE_CONFIG_FILE),
+        })?;
+    let tsig_key = read_state_file(dir, STATE_TSIG_KEY_FILE)?;
+    let htpasswd = read_state_file(dir, STATE_HTPASSWD_FILE)?;
+    // the whole config is parsed and validated before anything is written,
+    // so an invalid archive leaves the existing state untouched
+    let contents = String::from_utf8(contents)?;
+    let mut peach_config = deserialize_config(&contents, STATE_CONFIG_FILE)?;
+    validate(&peach_config)?;
+    // the old device was likely put into maintenance mode for the export,
+    // which should not carry over to the device being restored
+    peach_config.maintenance_mode = false;
+    // the tsig key is always restored to its default location, whatever path the archived
+    // config names, so that an archive cannot choose which file on the device is written
+    if !peach_config.dyn_tsig_key_path.is_empty() {
+        peach_config.dyn_tsig_key_path = TSIG_KEY_PATH.to_string();
+    }
+
+    // the config is saved first, as it is the step most likely to fail (e.g. on the config lock)
+    let peach_config = save_peach_config(peach_config)?;
+
+    if let Some(key) = tsig_key {
+        write_secret_file(TSIG_KEY_PATH, &key, TSIG_KEY_FILE_MODE)?;
+    }
+    if let Some(passwords) = htpasswd {
+        write_secret_file(&htpasswd_file(), &passwords, HTPASSWD_FILE_MODE)?;
+    }
+
+    Ok(peach_config)
+}
+
+// restores the state written by export_state. the archive is validated before anything is
+// extracted. unless force is true, importing over a device which has completed its setup
+// returns PeachError::DeviceAlreadyConfigured rather than replacing its state.
+pub fn import_state(path: &str, force: bool) -> Result<PeachConfig, PeachError> {
+    ensure_not_in_maintenance_mode()?;
+    if !force && is_setup_complete()? {
+        return Err(PeachError::DeviceAlreadyConfigured);
+    }
+    validate_state_archive(path)?;
+
+    let dir = create_staging_dir()?;
+    let dir_str = dir.to_string_lossy().to_string();
+    let result = run_tar(&["-xzf", path, "-C", &dir_str]).and_then(|_| restore_state(&dir));
+    if let Err(err) = fs::remove_dir_all(&dir) {
+        warn!(target: LOG_TARGET, "Failed to remove staging directory {}: {}", dir.display(), err);
+    }
+    result
 }
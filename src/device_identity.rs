@@ -0,0 +1,79 @@
+//! Assemble the identity of the PeachCloud device for display.
+//!
+//! The pieces of the device's identity are spread across the peach config, the
+//! system hostname and the sbot. `device_identity` gathers them in one call,
+//! treating the hostname and the ssb id as optional since they depend on the
+//! system and on the sbot running.
+
+use std::fs;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::config_manager::{load_peach_config, peachcloud_address};
+use crate::error::PeachError;
+use crate::sbot_client;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::device_identity=debug`.
+const LOG_TARGET: &str = "peach_lib::device_identity";
+
+pub const HOSTNAME_PATH: &str = "/etc/hostname";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeviceIdentity {
+    /// The best address to display for the device: the external domain, the
+    /// dyndns domain or the onion address, in that order of preference
+    /// (see `config_manager::peachcloud_address`).
+    pub address: Option<String>,
+    pub external_domain: Option<String>,
+    pub dyn_domain: Option<String>,
+    pub tor_address: Option<String>,
+    pub hostname: Option<String>,
+    pub device_name: Option<String>,
+    pub ssb_id: Option<String>,
+}
+
+/// Returns `None` for an empty config value.
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn hostname() -> Option<String> {
+    match fs::read_to_string(HOSTNAME_PATH) {
+        Ok(hostname) => non_empty(hostname.trim().to_string()),
+        Err(err) => {
+            debug!(target: LOG_TARGET, "Failed to read hostname: {}", err);
+            None
+        }
+    }
+}
+
+fn ssb_id() -> Option<String> {
+    match sbot_client::whoami() {
+        Ok(id) => Some(id),
+        Err(err) => {
+            debug!(target: LOG_TARGET, "Failed to get ssb id: {}", err);
+            None
+        }
+    }
+}
+
+/// Returns the identity of the device, assembled from the peach config, the
+/// hostname and the sbot. Fields which are not configured, or whose source is
+/// unavailable, are `None`. Only failing to load the config is an error.
+pub fn device_identity() -> Result<DeviceIdentity, PeachError> {
+    let peach_config = load_peach_config()?;
+    Ok(DeviceIdentity {
+        address: peachcloud_address(&peach_config),
+        external_domain: non_empty(peach_config.external_domain),
+        dyn_domain: non_empty(peach_config.dyn_domain),
+        tor_address: non_empty(peach_config.tor_address),
+        hostname: hostname(),
+        device_name: non_empty(peach_config.device_name),
+        ssb_id: ssb_id(),
+    })
+}
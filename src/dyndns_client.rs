@@ -9,30 +9,62 @@
 //!
 //! The domain for dyndns updates is stored in /var/lib/peachcloud/config.yml
 //! The tsig key for authenticating the updates is stored in /var/lib/peachcloud/peach-dyndns/tsig.key
-use crate::config_manager::{load_peach_config, set_peach_dyndns_config};
-use crate::error::PeachError;
+use crate::config_manager::{
+    ensure_not_in_maintenance_mode, load_peach_config, set_dyndns_enabled_value,
+    set_peach_dyndns_config, PeachConfig,
+};
+use crate::error::{rpc_error, PeachError};
 use crate::error::{
     ChronoParseError, DecodeNsUpdateOutputError, DecodePublicIpError, GetPublicIpError,
-    NsCommandError, SaveDynDnsResultError, SaveTsigKeyError,
+    NsCommandError, SaveDynDnsResultError, SaveTsigKeyError, StdIoError,
 };
+use crate::sbot_client;
+use crate::server_addresses::{ServerAddresses, DEFAULT_DYNDNS_SERVER};
+use crate::transport::http_transport;
 use chrono::prelude::*;
 use jsonrpc_client_core::{expand_params, jsonrpc_client};
-use jsonrpc_client_http::HttpTransport;
-use log::{debug, info};
+use jsonrpc_client_http::HttpHandle;
+use log::{debug, info, warn};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::process::{Command, Stdio};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::process::{Command, Output, Stdio};
 use std::str::FromStr;
 use std::str::ParseBoolError;
 
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::dyndns=debug`.
+const LOG_TARGET: &str = "peach_lib::dyndns";
+
+/// The name of the server called by this client, used in error messages.
+const SERVICE: &str = "peach-dyndns-server";
+
 /// constants for dyndns configuration
-pub const PEACH_DYNDNS_URL: &str = "http://dynserver.dyn.peachcloud.org";
+pub const PEACH_DYNDNS_URL: &str = DEFAULT_DYNDNS_SERVER;
 pub const TSIG_KEY_PATH: &str = "/var/lib/peachcloud/peach-dyndns/tsig.key";
 pub const PEACH_DYNDNS_CONFIG_PATH: &str = "/var/lib/peachcloud/peach-dyndns";
 pub const DYNDNS_LOG_PATH: &str = "/var/lib/peachcloud/peach-dyndns/latest_result.log";
+/// file recording the last public ip address seen by ip_changed_since_last_check
+pub const LAST_SEEN_IP_PATH: &str = "/var/lib/peachcloud/peach-dyndns/last_seen_ip";
+/// file recording the outcome of recent update attempts, one json object per line
+pub const DYNDNS_HISTORY_PATH: &str = "/var/lib/peachcloud/peach-dyndns/history.log";
+/// maximum number of attempts kept in the history file
+pub const DYNDNS_HISTORY_MAX_LINES: usize = 500;
+/// file recording consecutive failed updates, used for backoff and alerting
+pub const DYNDNS_FAILURE_STATE_PATH: &str = "/var/lib/peachcloud/peach-dyndns/failure_state.json";
+/// number of consecutive failed updates after which the ssb admins are alerted
+pub const DYNDNS_FAILURE_ALERT_THRESHOLD: u32 = 5;
+/// delay before retrying after the first failed update, doubling with each further failure
+pub const DYNDNS_BACKOFF_BASE_SECS: i64 = 60;
+/// maximum delay between retries of failed updates
+pub const DYNDNS_BACKOFF_MAX_SECS: i64 = 60 * 60;
+/// nameserver which accepts dynamic updates for dyndns domains
+pub const NAMESERVER: &str = "ns.peachcloud.org";
+/// file mode for the TSIG key (owner read/write only)
+pub const TSIG_KEY_FILE_MODE: u32 = 0o600;
 
 /// helper function which saves dyndns TSIG key returned by peach-dyndns-server to /var/lib/peachcloud/peach-dyndns/tsig.key
 pub fn save_dyndns_key(key: &str) -> Result<(), PeachError> {
@@ -44,6 +76,8 @@ pub fn save_dyndns_key(key: &str) -> Result<(), PeachError> {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
+        .mode(TSIG_KEY_FILE_MODE)
         .open(TSIG_KEY_PATH)
         .context(SaveTsigKeyError {
             path: TSIG_KEY_PATH.to_string(),
@@ -51,31 +85,52 @@ pub fn save_dyndns_key(key: &str) -> Result<(), PeachError> {
     writeln!(file, "{}", key).context(SaveTsigKeyError {
         path: TSIG_KEY_PATH.to_string(),
     })?;
+    // the mode given to open only applies when the file is created
+    fs::set_permissions(
+        TSIG_KEY_PATH,
+        fs::Permissions::from_mode(TSIG_KEY_FILE_MODE),
+    )
+    .context(SaveTsigKeyError {
+        path: TSIG_KEY_PATH.to_string(),
+    })?;
     Ok(())
 }
 
+/// Creates a JSON-RPC client with http transport for the `peach-dyndns-server`
+/// at the url given in `addresses`.
+pub fn dyndns_client_with(
+    addresses: &ServerAddresses,
+) -> std::result::Result<PeachDynDnsClient<HttpHandle>, PeachError> {
+    debug!(target: LOG_TARGET, "Creating HTTP transport for dyndns client.");
+    let transport = http_transport(None)?;
+    debug!(target: LOG_TARGET, "Creating HTTP transport handle on {}.", addresses.dyndns);
+    let transport_handle = transport.handle(&addresses.dyndns)?;
+    info!(target: LOG_TARGET, "Creating client for peach-dyndns service.");
+
+    Ok(PeachDynDnsClient::new(transport_handle))
+}
+
 /// Makes a post request to register a new domain with peach-dyns-server
 /// if the post is successful, the domain is registered with peach-dyndns-server
 /// a unique TSIG key is returned and saved to disk,
 /// and peachcloud is configured to start updating the IP of this domain using nsupdate
 pub fn register_domain(domain: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for dyndns client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_server = PEACH_DYNDNS_URL;
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(http_server)?;
-    info!("Creating client for peach-dyndns service.");
-    let mut client = PeachDynDnsClient::new(transport_handle);
-
-    info!("Performing register_domain call to peach-dyndns-server");
-    let res = client.register_domain(domain).call();
+    ensure_not_in_maintenance_mode()?;
+    let addresses = ServerAddresses::from_env();
+    let mut client = dyndns_client_with(&addresses)?;
+
+    info!(target: LOG_TARGET, "Performing register_domain call to peach-dyndns-server");
+    let res = client
+        .register_domain(domain)
+        .call()
+        .map_err(rpc_error(SERVICE));
     match res {
         Ok(key) => {
             // save new TSIG key
             save_dyndns_key(&key)?;
             // save new configuration values
             let set_config_result =
-                set_peach_dyndns_config(domain, PEACH_DYNDNS_URL, TSIG_KEY_PATH, true);
+                set_peach_dyndns_config(domain, &addresses.dyndns, TSIG_KEY_PATH, true);
             match set_config_result {
                 Ok(_) => {
                     let response = "success".to_string();
@@ -84,23 +139,20 @@ pub fn register_domain(domain: &str) -> std::result::Result<String, PeachError>
                 Err(err) => Err(err),
             }
         }
-        Err(err) => Err(PeachError::JsonRpcClientCore { source: err }),
+        Err(err) => Err(err),
     }
 }
 
 /// Makes a post request to check if a domain is available
 pub fn is_domain_available(domain: &str) -> std::result::Result<bool, PeachError> {
-    debug!("Creating HTTP transport for dyndns client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_server = PEACH_DYNDNS_URL;
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachDynDnsClient::new(transport_handle);
-
-    info!("Performing register_domain call to peach-dyndns-server");
-    let res = client.is_domain_available(domain).call();
-    info!("res: {:?}", res);
+    let mut client = dyndns_client_with(&ServerAddresses::from_env())?;
+
+    info!(target: LOG_TARGET, "Performing register_domain call to peach-dyndns-server");
+    let res = client
+        .is_domain_available(domain)
+        .call()
+        .map_err(rpc_error(SERVICE));
+    info!(target: LOG_TARGET, "res: {:?}", res);
     match res {
         Ok(result_str) => {
             let result: Result<bool, ParseBoolError> = FromStr::from_str(&result_str);
@@ -109,7 +161,7 @@ pub fn is_domain_available(domain: &str) -> std::result::Result<bool, PeachError
                 Err(err) => Err(PeachError::PeachParseBoolError { source: err }),
             }
         }
-        Err(err) => Err(PeachError::JsonRpcClientCore { source: err }),
+        Err(err) => Err(err),
     }
 }
 
@@ -124,12 +176,63 @@ fn get_public_ip_address() -> Result<String, PeachError> {
     Ok(command_output.to_string())
 }
 
+/// Checks the public ip address of the device against the last one seen,
+/// returning Some((old, new)) if it has changed and None if it has not.
+/// The last seen ip address is persisted, so changes are detected across reboots.
+/// The first check only records the current ip address and returns None.
+pub fn ip_changed_since_last_check() -> Result<Option<(String, String)>, PeachError> {
+    ip_changed_since_last_check_with(get_public_ip_address, LAST_SEEN_IP_PATH)
+}
+
+/// Same as ip_changed_since_last_check, but gets the public ip address from the given
+/// provider (e.g. to use a different lookup service) and records the last seen ip address
+/// in the given file
+pub fn ip_changed_since_last_check_with(
+    ip_provider: impl Fn() -> Result<String, PeachError>,
+    last_seen_ip_path: &str,
+) -> Result<Option<(String, String)>, PeachError> {
+    let current_ip = ip_provider()?.trim().to_string();
+    let last_seen_ip = fs::read_to_string(last_seen_ip_path)
+        .ok()
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+    if last_seen_ip.as_deref() == Some(current_ip.as_str()) {
+        return Ok(None);
+    }
+    if let Some(dir) = std::path::Path::new(last_seen_ip_path).parent() {
+        fs::create_dir_all(dir).context(StdIoError {
+            msg: "Failed to create dyndns config directory",
+        })?;
+    }
+    fs::write(last_seen_ip_path, &current_ip).context(StdIoError {
+        msg: "Failed to save last seen ip address",
+    })?;
+    Ok(last_seen_ip.map(|old_ip| (old_ip, current_ip)))
+}
+
+/// Helper function which runs nsupdate with the given tsig key, passing it the given commands via stdin
+fn nsupdate(tsig_key_path: &str, ns_commands: &str) -> Result<Output, PeachError> {
+    let nsupdate_command = Command::new("/usr/bin/nsupdate")
+        .arg("-k")
+        .arg(tsig_key_path)
+        .arg("-v")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(NsCommandError)?;
+    if let Some(mut stdin) = nsupdate_command.stdin.as_ref() {
+        write!(stdin, "{}", ns_commands).context(NsCommandError)?;
+    }
+    nsupdate_command.wait_with_output().context(NsCommandError)
+}
+
 /// Reads dyndns configurations from config.yml
 /// and then uses nsupdate to update the IP address for the configured domain
 pub fn dyndns_update_ip() -> Result<bool, PeachError> {
-    info!("Running dyndns_update_ip");
+    info!(target: LOG_TARGET, "Running dyndns_update_ip");
     let peach_config = load_peach_config()?;
-    info!(
+    info!(target: LOG_TARGET,
         "Using config:
     dyn_tsig_key_path: {:?}
     dyn_domain: {:?}
@@ -142,51 +245,311 @@ pub fn dyndns_update_ip() -> Result<bool, PeachError> {
         peach_config.dyn_enabled,
     );
     if !peach_config.dyn_enabled {
-        info!("dyndns is not enabled, not updating");
+        info!(target: LOG_TARGET, "dyndns is not enabled, not updating");
         Ok(false)
     } else {
-        // call nsupdate passing appropriate configs
-        let nsupdate_command = Command::new("/usr/bin/nsupdate")
-            .arg("-k")
-            .arg(peach_config.dyn_tsig_key_path)
-            .arg("-v")
-            .stdin(Stdio::piped())
-            .spawn()
-            .context(NsCommandError)?;
-        // pass nsupdate commands via stdin
-        let public_ip_address = get_public_ip_address()?;
-        info!("found public ip address: {}", public_ip_address);
-        let ns_commands = format!(
-            "
+        let mut failures = load_failure_state();
+        let now = Utc::now().timestamp();
+        if let Some(next_attempt) = failures.next_attempt() {
+            if now < next_attempt {
+                info!(target: LOG_TARGET,
+                    "backing off after {} consecutive failures, not updating until {}",
+                    failures.consecutive_failures, next_attempt
+                );
+                return Ok(false);
+            }
+        }
+        let result = nsupdate_ip(&peach_config);
+        match &result {
+            Ok(_) => clear_failure_state(),
+            Err(err) => record_failure(&mut failures, now, &peach_config.dyn_domain, err),
+        }
+        record_attempt(&result);
+        result
+    }
+}
+
+/// The state of dyndns after it was enabled with enable_and_verify
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DynDnsStatus {
+    pub domain: String,
+    pub enabled: bool,
+    /// seconds since the last successful update, if it is known
+    pub seconds_since_update: Option<i64>,
+}
+
+/// Enables dyndns for the registered domain and immediately runs an update,
+/// so that the caller can confirm it works rather than waiting for the next cron run.
+/// If the update fails, the enabled flag is rolled back to its previous value
+/// and the error from the update is returned (a failure to roll back is logged).
+pub fn enable_and_verify(domain: &str) -> Result<DynDnsStatus, PeachError> {
+    ensure_not_in_maintenance_mode()?;
+    let peach_config = load_peach_config()?;
+    if peach_config.dyn_domain != domain {
+        return Err(PeachError::DynDnsDomainNotRegistered {
+            domain: domain.to_string(),
+        });
+    }
+    let was_enabled = peach_config.dyn_enabled;
+    set_dyndns_enabled_value(true)?;
+    // an explicit request to enable dyndns should not wait for an earlier backoff to expire
+    clear_failure_state();
+    if let Err(err) = dyndns_update_ip() {
+        info!(target: LOG_TARGET, "dyndns update failed, rolling back enabled flag to {}", was_enabled);
+        // the caller needs the reason the update failed, so a failed rollback is only logged
+        if let Err(rollback_err) = set_dyndns_enabled_value(was_enabled) {
+            warn!(target: LOG_TARGET, "Failed to roll back dyndns enabled flag: {}", rollback_err);
+        }
+        return Err(err);
+    }
+    Ok(DynDnsStatus {
+        domain: domain.to_string(),
+        enabled: true,
+        seconds_since_update: get_num_seconds_since_successful_dns_update()?,
+    })
+}
+
+/// Helper function which uses nsupdate to point the configured domain at the public ip address
+fn nsupdate_ip(peach_config: &PeachConfig) -> Result<bool, PeachError> {
+    let public_ip_address = get_public_ip_address()?;
+    info!(target: LOG_TARGET, "found public ip address: {}", public_ip_address);
+    let ns_commands = format!(
+        "
+    server {NAMESERVER}
+    zone {ZONE}
+    update delete {DOMAIN} A
+    update add {DOMAIN} 30 A {PUBLIC_IP_ADDRESS}
+    send",
+        NAMESERVER = NAMESERVER,
+        ZONE = peach_config.dyn_domain,
+        DOMAIN = peach_config.dyn_domain,
+        PUBLIC_IP_ADDRESS = public_ip_address,
+    );
+    let nsupdate_output = nsupdate(&peach_config.dyn_tsig_key_path, &ns_commands)?;
+    info!(target: LOG_TARGET, "output: {:?}", nsupdate_output);
+    // We only return a successful result if nsupdate was successful
+    if nsupdate_output.status.success() {
+        info!(target: LOG_TARGET, "nsupdate succeeded, returning ok");
+        // log a timestamp that the update was successful
+        log_successful_nsupdate()?;
+        // notify the webhook, if one is configured, of the new ip
+        if !peach_config.dyn_update_webhook.is_empty() {
+            call_update_webhook(
+                &peach_config.dyn_update_webhook,
+                &peach_config.dyn_domain,
+                public_ip_address.trim(),
+            );
+        }
+        // return true
+        Ok(true)
+    } else {
+        info!(target: LOG_TARGET, "nsupdate failed, returning error");
+        let err_msg =
+            String::from_utf8(nsupdate_output.stdout).context(DecodeNsUpdateOutputError)?;
+        Err(PeachError::NsUpdateError { msg: err_msg })
+    }
+}
+
+/// Helper function which POSTs the new ip address and a timestamp to the configured webhook.
+/// Failures are logged but do not fail the update itself.
+fn call_update_webhook(webhook_url: &str, domain: &str, ip_address: &str) {
+    let payload = serde_json::json!({
+        "domain": domain,
+        "ip": ip_address,
+        "timestamp": Utc::now().to_rfc3339(),
+    });
+    let output = Command::new("/usr/bin/curl")
+        .arg("--silent")
+        .arg("--show-error")
+        .arg("--fail")
+        .arg("--max-time")
+        .arg("10")
+        .arg("--proto")
+        .arg("=http,https")
+        .arg("--header")
+        .arg("Content-Type: application/json")
+        .arg("--data")
+        .arg(payload.to_string())
+        // so that the url cannot be read as an option
+        .arg("--")
+        .arg(webhook_url)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            info!(target: LOG_TARGET, "notified dyndns update webhook: {}", webhook_url)
+        }
+        Ok(output) => warn!(target: LOG_TARGET,
+            "dyndns update webhook failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(err) => {
+            warn!(target: LOG_TARGET, "failed to run curl for dyndns update webhook: {}", err)
+        }
+    }
+}
+
+/// Consecutive dyndns update failures, persisted so that the updater can back off
+/// and alert the admins when updates keep failing
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct FailureState {
+    consecutive_failures: u32,
+    // unix timestamp of the latest failed update
+    last_failure: i64,
+}
+
+impl FailureState {
+    /// Returns the unix timestamp before which the next update should not be attempted.
+    /// The delay doubles with each consecutive failure, up to DYNDNS_BACKOFF_MAX_SECS.
+    fn next_attempt(&self) -> Option<i64> {
+        if self.consecutive_failures == 0 {
+            return None;
+        }
+        let exponent = (self.consecutive_failures - 1).min(16);
+        let delay = (DYNDNS_BACKOFF_BASE_SECS << exponent).min(DYNDNS_BACKOFF_MAX_SECS);
+        Some(self.last_failure + delay)
+    }
+}
+
+/// Helper function which loads the failure state, treating a missing or unreadable file as no failures
+fn load_failure_state() -> FailureState {
+    fs::read_to_string(DYNDNS_FAILURE_STATE_PATH)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Helper function which resets the failure count after a successful update
+fn clear_failure_state() {
+    if std::path::Path::new(DYNDNS_FAILURE_STATE_PATH).exists() {
+        if let Err(err) = fs::remove_file(DYNDNS_FAILURE_STATE_PATH) {
+            warn!(target: LOG_TARGET, "failed to clear dyndns failure state: {}", err);
+        }
+    }
+}
+
+/// Helper function which records a failed update, and alerts the ssb admins
+/// once DYNDNS_FAILURE_ALERT_THRESHOLD consecutive updates have failed
+fn record_failure(failures: &mut FailureState, now: i64, domain: &str, err: &PeachError) {
+    failures.consecutive_failures += 1;
+    failures.last_failure = now;
+    let saved = serde_json::to_string(failures)
+        .map_err(PeachError::from)
+        .and_then(|json| fs::write(DYNDNS_FAILURE_STATE_PATH, json).context(SaveDynDnsResultError));
+    if let Err(save_err) = saved {
+        warn!(target: LOG_TARGET, "failed to save dyndns failure state: {}", save_err);
+    }
+    if failures.consecutive_failures == DYNDNS_FAILURE_ALERT_THRESHOLD {
+        alert_admins(&format!(
+            "Dynamic dns updates for {} have failed {} times in a row. The latest error was: {}",
+            domain, failures.consecutive_failures, err
+        ));
+    }
+}
+
+/// Helper function which sends an alert to each ssb admin via private message.
+/// This is best-effort: failing to send an alert is logged.
+fn alert_admins(msg: &str) {
+    warn!(target: LOG_TARGET, "{}", msg);
+    let admin_ids = match load_peach_config() {
+        Ok(peach_config) => peach_config.ssb_admin_ids,
+        Err(err) => {
+            warn!(target: LOG_TARGET, "failed to load ssb admins to alert: {}", err);
+            return;
+        }
+    };
+    for admin_id in admin_ids {
+        if let Err(err) = sbot_client::private_message(msg, &admin_id) {
+            warn!(target: LOG_TARGET, "failed to alert {}: {}", admin_id, err);
+        }
+    }
+}
+
+/// Checks whether the configured tsig key is accepted by the nameserver, without changing dns.
+/// This sends an update which adds and deletes a TXT record in the same transaction,
+/// so that a bad key is caught before the updater silently fails.
+/// Returns Ok(false) if the nameserver rejects the key, and an error if nsupdate fails for another reason.
+pub fn test_tsig_key() -> Result<bool, PeachError> {
+    let peach_config = load_peach_config()?;
+    let ns_commands = format!(
+        "
         server {NAMESERVER}
         zone {ZONE}
-        update delete {DOMAIN} A
-        update add {DOMAIN} 30 A {PUBLIC_IP_ADDRESS}
+        update add {TEST_RECORD} 30 TXT \"peach tsig key test\"
+        update delete {TEST_RECORD} TXT
         send",
-            NAMESERVER = "ns.peachcloud.org",
-            ZONE = peach_config.dyn_domain,
-            DOMAIN = peach_config.dyn_domain,
-            PUBLIC_IP_ADDRESS = public_ip_address,
-        );
-        write!(nsupdate_command.stdin.as_ref().unwrap(), "{}", ns_commands).unwrap();
-        let nsupdate_output = nsupdate_command
-            .wait_with_output()
-            .context(NsCommandError)?;
-        info!("output: {:?}", nsupdate_output);
-        // We only return a successful result if nsupdate was successful
-        if nsupdate_output.status.success() {
-            info!("nsupdate succeeded, returning ok");
-            // log a timestamp that the update was successful
-            log_successful_nsupdate()?;
-            // return true
-            Ok(true)
-        } else {
-            info!("nsupdate failed, returning error");
-            let err_msg =
-                String::from_utf8(nsupdate_output.stdout).context(DecodeNsUpdateOutputError)?;
-            Err(PeachError::NsUpdateError { msg: err_msg })
-        }
+        NAMESERVER = NAMESERVER,
+        ZONE = peach_config.dyn_domain,
+        TEST_RECORD = format!("_peach-tsig-test.{}", peach_config.dyn_domain),
+    );
+    let nsupdate_output = nsupdate(&peach_config.dyn_tsig_key_path, &ns_commands)?;
+    if nsupdate_output.status.success() {
+        return Ok(true);
     }
+    let stdout = String::from_utf8(nsupdate_output.stdout).context(DecodeNsUpdateOutputError)?;
+    let stderr = String::from_utf8(nsupdate_output.stderr).context(DecodeNsUpdateOutputError)?;
+    let output = format!("{}{}", stdout, stderr);
+    // the nameserver responds NOTAUTH to an unknown key, and reports BADSIG or BADKEY for a wrong one
+    if ["NOTAUTH", "BADSIG", "BADKEY"]
+        .iter()
+        .any(|rcode| output.contains(rcode))
+    {
+        info!(target: LOG_TARGET, "tsig key was rejected: {}", output);
+        Ok(false)
+    } else {
+        Err(PeachError::NsUpdateError { msg: output })
+    }
+}
+
+/// The outcome of a dyndns update attempt, as recorded in the history file
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DynDnsAttempt {
+    /// rfc3339 timestamp of the attempt
+    pub timestamp: String,
+    pub success: bool,
+    /// the error, if the attempt failed
+    pub error: Option<String>,
+}
+
+/// Helper function which appends an update attempt to the history file,
+/// dropping the oldest attempts once it holds more than DYNDNS_HISTORY_MAX_LINES.
+/// Failing to record the attempt is logged but does not fail the update.
+fn record_attempt(result: &Result<bool, PeachError>) {
+    let attempt = DynDnsAttempt {
+        timestamp: Utc::now().to_rfc3339(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|err| err.to_string()),
+    };
+    let saved = serde_json::to_string(&attempt)
+        .map_err(PeachError::from)
+        .and_then(|line| {
+            let history = fs::read_to_string(DYNDNS_HISTORY_PATH).unwrap_or_default();
+            let mut lines: Vec<&str> = history.lines().collect();
+            lines.push(&line);
+            let start = lines.len().saturating_sub(DYNDNS_HISTORY_MAX_LINES);
+            let mut contents = lines[start..].join("\n");
+            contents.push('\n');
+            fs::write(DYNDNS_HISTORY_PATH, contents).context(SaveDynDnsResultError)
+        });
+    if let Err(err) = saved {
+        warn!(target: LOG_TARGET, "failed to record dyndns update attempt: {}", err);
+    }
+}
+
+/// Returns up to `limit` of the most recent dyndns update attempts, newest first
+pub fn dyndns_history(limit: usize) -> Result<Vec<DynDnsAttempt>, PeachError> {
+    if !std::path::Path::new(DYNDNS_HISTORY_PATH).exists() {
+        return Ok(Vec::new());
+    }
+    let history = fs::read_to_string(DYNDNS_HISTORY_PATH).context(StdIoError {
+        msg: "Failed to read dyndns history",
+    })?;
+    let attempts = history
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .take(limit)
+        .collect();
+    Ok(attempts)
 }
 
 // Helper function to log a timestamp of the latest successful nsupdate
@@ -195,6 +558,7 @@ pub fn log_successful_nsupdate() -> Result<bool, PeachError> {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(DYNDNS_LOG_PATH)
         .context(SaveDynDnsResultError)?;
     write!(file, "{}", now_timestamp).context(SaveDynDnsResultError)?;
@@ -229,19 +593,14 @@ pub fn is_dns_updater_online() -> Result<bool, PeachError> {
     let is_enabled = peach_config.dyn_enabled;
     // then check if it has successfully run within the last 6 minutes (60*6 seconds)
     let num_seconds_since_successful_update = get_num_seconds_since_successful_dns_update()?;
-    let ran_recently: bool;
-    match num_seconds_since_successful_update {
-        Some(seconds) => {
-            ran_recently = seconds < (60 * 6);
-        }
+    let ran_recently = match num_seconds_since_successful_update {
+        Some(seconds) => seconds < (60 * 6),
         // if the value is None, then the last time it ran successfully is unknown
-        None => {
-            ran_recently = false;
-        }
-    }
+        None => false,
+    };
     // debug log
-    info!("is_dyndns_enabled: {:?}", is_enabled);
-    info!("dyndns_ran_recently: {:?}", ran_recently);
+    info!(target: LOG_TARGET, "is_dyndns_enabled: {:?}", is_enabled);
+    info!(target: LOG_TARGET, "dyndns_ran_recently: {:?}", ran_recently);
     // if both are true, then return true
     Ok(is_enabled && ran_recently)
 }
@@ -11,8 +11,43 @@ pub enum PeachError {
     JsonRpcHttp { source: jsonrpc_client_http::Error },
     #[snafu(display("{}", source))]
     JsonRpcClientCore { source: jsonrpc_client_core::Error },
+    #[snafu(display("Timed out waiting for a JSON-RPC response"))]
+    Timeout,
+    #[snafu(display("The {} microservice is not running", service))]
+    ServiceUnavailable { service: String },
     #[snafu(display("{}", source))]
     Serde { source: serde_json::error::Error },
+    #[snafu(display("No traffic data is available for interface: {}", iface))]
+    NoTrafficData { iface: String },
+    #[snafu(display(
+        "Failed to update network credentials at the {} step: {}",
+        step,
+        source
+    ))]
+    NetworkUpdateFailed {
+        step: String,
+        report: crate::network_client::UpdateReport,
+        source: Box<PeachError>,
+    },
+    #[snafu(display("Invalid IP address for {}: {:?}: {}", iface, value, source))]
+    InvalidIpAddress {
+        source: std::net::AddrParseError,
+        iface: String,
+        value: String,
+    },
+    #[snafu(display("Invalid signal strength for {}: {:?}: {}", iface, value, source))]
+    InvalidSignalStrength {
+        source: std::num::ParseIntError,
+        iface: String,
+        value: String,
+    },
+    #[snafu(display(
+        "Invalid country code, must be two letters (ISO 3166-1 alpha-2): {}",
+        code
+    ))]
+    InvalidCountryCode { code: String },
+    #[snafu(display("No such network interface: {}", iface))]
+    InterfaceNotFound { iface: String },
     #[snafu(display("{}", source))]
     PeachParseBoolError { source: std::str::ParseBoolError },
     #[snafu(display("{}", source))]
@@ -22,6 +57,8 @@ pub enum PeachError {
         source: std::io::Error,
         file: String,
     },
+    #[snafu(display("Timed out waiting for config lock: {}", path))]
+    ConfigLockTimeout { path: String },
     #[snafu(display("Failed to save: {}", file))]
     WriteConfigError {
         source: std::io::Error,
@@ -34,6 +71,8 @@ pub enum PeachError {
     },
     #[snafu(display("{}", msg))]
     NsUpdateError { msg: String },
+    #[snafu(display("The dyndns domain {} is not registered on this device", domain))]
+    DynDnsDomainNotRegistered { domain: String },
     #[snafu(display("Failed to run nsupdate: {}", source))]
     NsCommandError { source: std::io::Error },
     #[snafu(display("Failed to get public IP address: {}", source))]
@@ -44,6 +83,10 @@ pub enum PeachError {
     DecodeNsUpdateOutputError { source: std::string::FromUtf8Error },
     #[snafu(display("{}", source))]
     YamlError { source: serde_yaml::Error },
+    #[snafu(display("Failed to parse toml config: {}", msg))]
+    TomlError { msg: String },
+    #[snafu(display("Unsupported config format (enable the toml-config feature): {}", file))]
+    UnsupportedConfigFormat { file: String },
     #[snafu(display("{:?}", err))]
     JsonRpcCore { err: jsonrpc_core::Error },
     #[snafu(display("Error creating regex: {}", source))]
@@ -65,12 +108,97 @@ pub enum PeachError {
     PasswordsDoNotMatch,
     #[snafu(display("The supplied password was not correct"))]
     InvalidPassword,
+    #[snafu(display("The password reset token is invalid or has expired"))]
+    InvalidResetToken,
+    #[snafu(display(
+        "Too many failed password attempts, try again in {} seconds",
+        retry_after_secs
+    ))]
+    TooManyAttempts { retry_after_secs: u64 },
+    #[snafu(display("The new password was used recently, please choose a different one"))]
+    PasswordReused,
     #[snafu(display("Error saving new password: {}", msg))]
     FailedToSetNewPassword { msg: String },
+    #[snafu(display("The device is in maintenance mode, changes are not allowed"))]
+    MaintenanceMode,
+    #[snafu(display("Invalid state archive: {}", msg))]
+    StateArchiveError { msg: String },
+    #[snafu(display("Refusing to import state over a configured device without force"))]
+    DeviceAlreadyConfigured,
+    #[snafu(display("Error sending email: {}", msg))]
+    SendEmailError { msg: String },
+    #[snafu(display("No ssb admin ids are configured"))]
+    NoSsbAdminIds,
+    #[snafu(display("The sbot is not running"))]
+    SbotOffline,
+    #[snafu(display("The sbot is not following the ssb admin: {}", id))]
+    AdminNotFollowed { id: String },
+    #[snafu(display("Publishing too quickly, try again in {} seconds", retry_after_secs))]
+    PublishRateLimited { retry_after_secs: u64 },
     #[snafu(display("Error calling sbotcli: {}", msg))]
     SbotCliError { msg: String },
+    #[snafu(display("The installed sbotcli does not support {}", feature))]
+    Unsupported { feature: String },
+    #[snafu(display("Invalid ssb id: {}", id))]
+    InvalidSsbId { id: String },
+    #[snafu(display("Not a png, jpeg, gif or webp image: {}", path))]
+    InvalidImage { path: String },
+    #[snafu(display("Invalid invite code: {}", code))]
+    InvalidInviteCode { code: String },
+    #[snafu(display("Invalid multiserver address: {}", address))]
+    InvalidMultiserverAddress { address: String },
+    #[snafu(display(
+        "Refusing to check the RPC contract against {:?}: an unsaved test network must be given",
+        ssid
+    ))]
+    ContractTestNetworkRequired { ssid: String },
     #[snafu(display("Error deleting ssb admin id, id not found"))]
     SsbAdminIdNotFound { id: String },
+    #[snafu(display("Invalid tor address, must be a v3 .onion address: {}", address))]
+    InvalidOnionAddress { address: String },
+    #[snafu(display("Device name must be at most {} characters", max_length))]
+    DeviceNameTooLong { max_length: usize },
+    #[snafu(display("Invalid webhook, must be an http:// or https:// url: {}", url))]
+    InvalidWebhookUrl { url: String },
+    #[snafu(display("Invalid email address: {}", address))]
+    InvalidEmailAddress { address: String },
+    #[snafu(display("Unsupported OLED font size: {}", font_size))]
+    InvalidFontSize { font_size: String },
+    #[snafu(display("Unsupported OLED rotation: {} (expected 0 or 180)", rotation))]
+    UnsupportedRotation { rotation: u16 },
+    #[snafu(display("Data of length {} is too long to fit in a QR code on the OLED", len))]
+    QrCodeTooLarge { len: usize },
+}
+
+impl PeachError {
+    /// Returns true if the error is likely to be transient, such as a microservice
+    /// which timed out or is restarting, so that the operation may succeed if retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            PeachError::Timeout
+                | PeachError::ServiceUnavailable { .. }
+                | PeachError::JsonRpcHttp { .. }
+                | PeachError::ConfigLockTimeout { .. }
+                | PeachError::SbotOffline
+        )
+    }
+
+    /// Returns true if the error means a JSON-RPC call did not get an answer from the
+    /// microservice (it was not running, timed out or the http request failed), rather
+    /// than the microservice answering with an error or a malformed response.
+    pub fn is_transport_error(&self) -> bool {
+        match self {
+            PeachError::Timeout
+            | PeachError::ServiceUnavailable { .. }
+            | PeachError::JsonRpcHttp { .. } => true,
+            PeachError::JsonRpcClientCore { source } => matches!(
+                source.kind(),
+                jsonrpc_client_core::ErrorKind::TransportError
+            ),
+            _ => false,
+        }
+    }
 }
 
 impl From<jsonrpc_client_http::Error> for PeachError {
@@ -79,9 +207,59 @@ impl From<jsonrpc_client_http::Error> for PeachError {
     }
 }
 
+/// Returns true if a JSON-RPC client error was caused by the http transport
+/// timing out while waiting for a response.
+fn is_transport_timeout(err: &jsonrpc_client_core::Error) -> bool {
+    match err.1.next_error.as_ref() {
+        Some(cause) => match cause.downcast_ref::<jsonrpc_client_http::Error>() {
+            Some(http_err) => matches!(
+                http_err.kind(),
+                jsonrpc_client_http::ErrorKind::RequestTimeout
+            ),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// Returns true if a JSON-RPC client error was caused by the connection to the
+/// microservice being refused, which means the service is not running.
+fn is_connection_refused(err: &jsonrpc_client_core::Error) -> bool {
+    let http_err = match err.1.next_error.as_ref() {
+        Some(cause) => cause.downcast_ref::<jsonrpc_client_http::Error>(),
+        None => None,
+    };
+    match http_err.map(|http_err| http_err.kind()) {
+        Some(jsonrpc_client_http::ErrorKind::Hyper(hyper::Error::Io(io_err))) => {
+            io_err.kind() == std::io::ErrorKind::ConnectionRefused
+        }
+        _ => false,
+    }
+}
+
+/// Returns a closure converting a JSON-RPC client error into a `PeachError`,
+/// naming the given microservice if it could not be reached.
+pub(crate) fn rpc_error(
+    service: &'static str,
+) -> impl Fn(jsonrpc_client_core::Error) -> PeachError {
+    move |err| {
+        if is_connection_refused(&err) {
+            PeachError::ServiceUnavailable {
+                service: service.to_string(),
+            }
+        } else {
+            PeachError::from(err)
+        }
+    }
+}
+
 impl From<jsonrpc_client_core::Error> for PeachError {
     fn from(err: jsonrpc_client_core::Error) -> PeachError {
-        PeachError::JsonRpcClientCore { source: err }
+        if is_transport_timeout(&err) {
+            PeachError::Timeout
+        } else {
+            PeachError::JsonRpcClientCore { source: err }
+        }
     }
 }
 
@@ -132,3 +310,75 @@ impl From<chrono::ParseError> for PeachError {
         }
     }
 }
+
+/// Error code for a JSON-RPC request which timed out waiting for another microservice.
+pub const TIMEOUT_ERROR_CODE: i64 = -32001;
+/// Error code for a JSON-RPC request which required a microservice that is not running.
+pub const SERVICE_UNAVAILABLE_ERROR_CODE: i64 = -32002;
+/// Error code for a JSON-RPC request rejected after too many failed password attempts.
+pub const TOO_MANY_ATTEMPTS_ERROR_CODE: i64 = -32003;
+/// Error code for a JSON-RPC request rejected because the device is in maintenance mode.
+pub const MAINTENANCE_MODE_ERROR_CODE: i64 = -32004;
+/// Error code for a JSON-RPC request rejected because ssb messages are being published too quickly.
+pub const PUBLISH_RATE_LIMITED_ERROR_CODE: i64 = -32005;
+
+/// Converts a `PeachError` into a JSON-RPC error, so that peach microservices
+/// can return errors from this library to their own callers. Errors caused by
+/// invalid input are reported as `InvalidParams`, and unexpected failures as
+/// `InternalError`.
+impl From<PeachError> for jsonrpc_core::Error {
+    fn from(err: PeachError) -> jsonrpc_core::Error {
+        let message = err.to_string();
+        let (code, data) = match err {
+            PeachError::JsonRpcCore { err } => return err,
+            PeachError::Timeout => (
+                jsonrpc_core::ErrorCode::ServerError(TIMEOUT_ERROR_CODE),
+                None,
+            ),
+            PeachError::ServiceUnavailable { service } => (
+                jsonrpc_core::ErrorCode::ServerError(SERVICE_UNAVAILABLE_ERROR_CODE),
+                Some(serde_json::json!({ "service": service })),
+            ),
+            PeachError::TooManyAttempts { retry_after_secs } => (
+                jsonrpc_core::ErrorCode::ServerError(TOO_MANY_ATTEMPTS_ERROR_CODE),
+                Some(serde_json::json!({ "retry_after_secs": retry_after_secs })),
+            ),
+            PeachError::PublishRateLimited { retry_after_secs } => (
+                jsonrpc_core::ErrorCode::ServerError(PUBLISH_RATE_LIMITED_ERROR_CODE),
+                Some(serde_json::json!({ "retry_after_secs": retry_after_secs })),
+            ),
+            PeachError::MaintenanceMode => (
+                jsonrpc_core::ErrorCode::ServerError(MAINTENANCE_MODE_ERROR_CODE),
+                None,
+            ),
+            PeachError::PasswordsDoNotMatch
+            | PeachError::InvalidPassword
+            | PeachError::InvalidResetToken
+            | PeachError::PasswordReused
+            | PeachError::InvalidSsbId { .. }
+            | PeachError::InvalidImage { .. }
+            | PeachError::InvalidInviteCode { .. }
+            | PeachError::InvalidMultiserverAddress { .. }
+            | PeachError::SsbAdminIdNotFound { .. }
+            | PeachError::ContractTestNetworkRequired { .. }
+            | PeachError::InterfaceNotFound { .. }
+            | PeachError::InvalidCountryCode { .. }
+            | PeachError::DynDnsDomainNotRegistered { .. }
+            | PeachError::StateArchiveError { .. }
+            | PeachError::DeviceAlreadyConfigured
+            | PeachError::InvalidOnionAddress { .. }
+            | PeachError::DeviceNameTooLong { .. }
+            | PeachError::InvalidWebhookUrl { .. }
+            | PeachError::InvalidEmailAddress { .. }
+            | PeachError::InvalidFontSize { .. }
+            | PeachError::UnsupportedRotation { .. }
+            | PeachError::QrCodeTooLarge { .. } => (jsonrpc_core::ErrorCode::InvalidParams, None),
+            _ => (jsonrpc_core::ErrorCode::InternalError, None),
+        };
+        jsonrpc_core::Error {
+            code,
+            message,
+            data,
+        }
+    }
+}
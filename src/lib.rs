@@ -1,15 +1,35 @@
+//! Client libraries for the PeachCloud microservices, config and sbot.
+//!
+//! Each module logs with its own target, so logging can be enabled per module
+//! with `RUST_LOG`, e.g. `RUST_LOG=peach_lib::network=debug,peach_lib::dyndns=info`.
+//! The targets are `peach_lib::config`, `peach_lib::device_identity`,
+//! `peach_lib::dyndns`, `peach_lib::network`, `peach_lib::oled`,
+//! `peach_lib::password`, `peach_lib::retry`, `peach_lib::sbot`,
+//! `peach_lib::stats` and `peach_lib::version`.
+//!
+//! JSON-RPC requests time out after `PEACH_RPC_TIMEOUT_MS` milliseconds
+//! (see `transport`).
+
 // this is to ignore a clippy warning that suggests
 // to replace code with the same code that is already there (possibly a bug)
 #![allow(clippy::nonstandard_macro_braces)]
 
 pub mod config_manager;
+pub mod device_identity;
 pub mod dyndns_client;
 pub mod error;
 pub mod network_client;
 pub mod oled_client;
 pub mod password_utils;
+pub mod retry;
 pub mod sbot_client;
+pub mod server_addresses;
 pub mod stats_client;
+pub mod transport;
+pub mod version;
+
+pub use server_addresses::ServerAddresses;
+pub use version::{version, VersionInfo};
 
 // re-export error types
 pub use jsonrpc_client_core;
@@ -7,20 +7,39 @@
 //! methods simplify the process of performing RPC calls from other modules.
 //!
 //! Several helper methods are also included here which bundle multiple client
-//! calls to achieve the desired functionality.
+//! calls to achieve the desired functionality. These share a single client,
+//! created with `network_client`, which callers can also hold on to in order
+//! to make several calls without creating a new transport for each one. Each
+//! helper has a `_with` variant (e.g. `forget_with`) which takes such a client
+//! rather than creating its own.
 
 // TODO: fix these clippy errors so this allow can be removed
 #![allow(clippy::needless_borrow)]
 
+use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use jsonrpc_client_core::{expand_params, jsonrpc_client};
-use jsonrpc_client_http::HttpTransport;
-use log::{debug, info};
+use jsonrpc_client_http::HttpHandle;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
 
-use crate::error::PeachError;
+use crate::config_manager;
+use crate::error::{rpc_error, InvalidIpAddress, InvalidSignalStrength, PeachError, StdIoError};
+use crate::retry::{rpc_retries, with_retries};
+use crate::server_addresses::ServerAddresses;
 use crate::stats_client::Traffic;
+use crate::transport::http_transport;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::network=debug`.
+const LOG_TARGET: &str = "peach_lib::network";
+
+/// The name of the microservice called by this client, used in error messages.
+const SERVICE: &str = "peach-network";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AccessPoint {
@@ -42,20 +61,67 @@ pub struct Scan {
     pub ssid: String,
 }
 
+/// Creates a JSON-RPC client with http transport for the `peach-network`
+/// microservice, at the address given by the `PEACH_NETWORK_SERVER`
+/// environment variable (`127.0.0.1:5110` by default).
+///
+/// Each of the convenience functions in this module creates a new client (and
+/// with it a new transport thread) for every call. When making several calls in
+/// a row, such as when building a status page, create one client with this
+/// function and reuse it for all of them instead. The client's methods return
+/// `RpcRequest`s which are sent by calling `call()`.
+///
+/// # Example
+///
+/// ```no_run
+/// use peach_lib::error::PeachError;
+/// use peach_lib::network_client::network_client;
+///
+/// # fn main() -> Result<(), PeachError> {
+/// let mut client = network_client()?;
+/// let ssid = client.ssid("wlan0").call()?;
+/// let ip = client.ip("wlan0").call()?;
+/// let rssi = client.rssi("wlan0").call()?;
+/// println!("connected to {} as {} ({} dBm)", ssid, ip, rssi);
+/// # Ok(())
+/// # }
+/// ```
+pub fn network_client() -> std::result::Result<PeachNetworkClient<HttpHandle>, PeachError> {
+    network_client_with(&ServerAddresses::from_env())
+}
+
+/// Creates a JSON-RPC client with http transport for the `peach-network`
+/// microservice at the address given in `addresses`.
+pub fn network_client_with(
+    addresses: &ServerAddresses,
+) -> std::result::Result<PeachNetworkClient<HttpHandle>, PeachError> {
+    network_client_at(&addresses.network)
+}
+
+/// Creates a JSON-RPC client with http transport for the `peach-network`
+/// microservice at the given address (`host:port`), e.g. to talk to several
+/// instances from one process. The client can be passed to the `_with`
+/// helpers, such as `forget_with`.
+pub fn network_client_at(
+    addr: &str,
+) -> std::result::Result<PeachNetworkClient<HttpHandle>, PeachError> {
+    debug!(target: LOG_TARGET, "Creating HTTP transport for network client.");
+    let transport = http_transport(None)?;
+    let http_server = format!("http://{}", addr);
+    debug!(target: LOG_TARGET, "Creating HTTP transport handle on {}.", http_server);
+    let transport_handle = transport.handle(&http_server)?;
+    info!(target: LOG_TARGET, "Creating client for peach_network service.");
+
+    Ok(PeachNetworkClient::new(transport_handle))
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `activate_ap` method.
 pub fn activate_ap() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
 
-    let response = client.activate_ap().call()?;
+    let response = client.activate_ap().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -63,44 +129,144 @@ pub fn activate_ap() -> std::result::Result<String, PeachError> {
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `activate_client` method.
 pub fn activate_client() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
 
-    let response = client.activate_client().call()?;
+    let response = client
+        .activate_client()
+        .call()
+        .map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// The wireless interface whose credentials are replaced when `add` is called
+/// for a network which has already been saved.
+const WIFI_IFACE: &str = "wlan0";
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `add_wifi` method.
 ///
+/// Adding is idempotent: if credentials are already saved for the SSID (see
+/// `saved_ap`), they are replaced with the given password through `update`
+/// instead of a duplicate entry being added to `wpa_supplicant.conf`.
+///
 /// # Arguments
 ///
 /// * `ssid` - A string slice containing the SSID of an access point.
 /// * `pass` - A string slice containing the password for an access point.
 pub fn add(ssid: &str, pass: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.add(ssid, pass).call()?;
+    add_with(&mut client, ssid, pass)
+}
+
+/// Same as `add`, but makes the calls with the given client.
+pub fn add_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+
+    if saved_ap_with(client, ssid)? {
+        info!(target: LOG_TARGET, "Credentials for {} are already saved; updating them.", ssid);
+        update_with(client, WIFI_IFACE, ssid, pass)?;
+        return Ok("success".to_string());
+    }
+
+    let response = client.add(ssid, pass).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `add`, `save`, `id` and `connect` methods, to join a network which has not
+/// been saved before in a single call.
+///
+/// If the `save`, `id` or `connect` step fails, the credentials which were just
+/// added are deleted again (with the `delete` and `save` methods) before the
+/// error is returned, so a failed attempt does not leave a network behind in
+/// `wpa_supplicant.conf`. If the rollback fails as well, a warning is logged
+/// and the credentials may remain saved; `saved_ap` can be used to check.
+///
+/// If credentials for the SSID are already saved, they are replaced as in
+/// `add` and are not deleted if connecting fails.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+/// * `ssid` - A string slice containing the SSID of an access point.
+/// * `pass` - A string slice containing the password for an access point.
+pub fn add_and_connect(
+    iface: &str,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<String, PeachError> {
+    let mut client = network_client()?;
+
+    add_and_connect_with(&mut client, iface, ssid, pass)
+}
+
+/// Saves the networks and connects to the given one, for `add_and_connect`.
+fn save_and_connect(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+) -> std::result::Result<String, PeachError> {
+    info!(target: LOG_TARGET, "Performing save call to peach-network microservice.");
+    client.save().call().map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing id call to peach-network microservice.");
+    let id = client.id(iface, ssid).call().map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing connect call to peach-network microservice.");
+    let response = client
+        .connect(&id, iface)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
+/// Same as `add_and_connect`, but makes the calls with the given client.
+pub fn add_and_connect_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+
+    let newly_added = !saved_ap_with(client, ssid)?;
+    if newly_added {
+        info!(target: LOG_TARGET, "Performing add call to peach-network microservice.");
+        client.add(ssid, pass).call().map_err(rpc_error(SERVICE))?;
+    } else {
+        info!(target: LOG_TARGET, "Credentials for {} are already saved; updating them.", ssid);
+        update_with(client, iface, ssid, pass)?;
+    }
+
+    let result = save_and_connect(client, iface, ssid);
+
+    if let Err(err) = &result {
+        if newly_added {
+            warn!(target: LOG_TARGET, "Failed to connect to {}, removing it again: {}", ssid, err);
+            let rollback = client
+                .id(iface, ssid)
+                .call()
+                .and_then(|id| client.delete(iface, &id).call())
+                .and_then(|_| client.save().call());
+            if let Err(rollback_err) = rollback {
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to remove the credentials for {}: {}", ssid, rollback_err
+                );
+            }
+        }
+    }
+
+    result
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `available_networks` method, which returns a list of in-range access points.
 ///
@@ -108,21 +274,63 @@ pub fn add(ssid: &str, pass: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn available_networks(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.available_networks(iface).call()?;
+    let response = client
+        .available_networks(iface)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `available_networks` method, returning the in-range access points parsed
+/// into `Scan` structs. Malformed data returns `PeachError::Serde`.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn available_networks_parsed(iface: &str) -> std::result::Result<Vec<Scan>, PeachError> {
+    let response = available_networks(iface)?;
+    let networks: Vec<Scan> = serde_json::from_str(&response)?;
+
+    Ok(networks)
+}
+
+/// Returns the signal level of a scanned access point in dBm. Levels which
+/// are not a number are treated as the weakest possible signal.
+fn signal_level(scan: &Scan) -> i32 {
+    scan.signal_level.trim().parse().unwrap_or(i32::MIN)
+}
+
+/// Like `available_networks_parsed`, but with a single entry for each SSID
+/// (the access point with the strongest signal, when several are in range),
+/// sorted by signal strength with the strongest first.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn available_networks_sorted(iface: &str) -> std::result::Result<Vec<Scan>, PeachError> {
+    let mut strongest: HashMap<String, Scan> = HashMap::new();
+    for scan in available_networks_parsed(iface)? {
+        match strongest.get(&scan.ssid) {
+            Some(existing) if signal_level(existing) >= signal_level(&scan) => {}
+            _ => {
+                strongest.insert(scan.ssid.clone(), scan);
+            }
+        }
+    }
+    let mut networks: Vec<Scan> = strongest.into_values().collect();
+    networks.sort_by(|a, b| {
+        signal_level(b)
+            .cmp(&signal_level(a))
+            .then(a.ssid.cmp(&b.ssid))
+    });
+
+    Ok(networks)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `connect` method, which disables other network connections and enables the
 /// connection for the chosen network, identified by ID and interface.
@@ -131,20 +339,16 @@ pub fn available_networks(iface: &str) -> std::result::Result<String, PeachError
 ///
 /// * `id` - A string slice containing a network identifier.
 /// * `iface` - A string slice containing the network interface identifier.
+///
+/// The call is retried if `peach-network` is not running, when
+/// `PEACH_RPC_RETRIES` is set (see `retry::with_retries`).
 pub fn connect(id: &str, iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
 
-    let response = client.connect(id, iface).call()?;
-
-    Ok(response)
+    with_retries(rpc_retries().saturating_add(1), || {
+        client.connect(id, iface).call().map_err(rpc_error(SERVICE))
+    })
 }
 
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
@@ -155,26 +359,95 @@ pub fn connect(id: &str, iface: &str) -> std::result::Result<String, PeachError>
 /// * `iface` - A string slice containing the network interface identifier.
 /// * `ssid` - A string slice containing the SSID of a network.
 pub fn disable(iface: &str, ssid: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
+
+    disable_with(&mut client, iface, ssid)
+}
 
-    info!("Performing id call to peach-network microservice.");
-    let id = client.id(&iface, &ssid).call()?;
-    info!("Performing disable call to peach-network microservice.");
-    client.disable(&id, &iface).call()?;
+/// Same as `disable`, but makes the calls with the given client.
+pub fn disable_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    info!(target: LOG_TARGET, "Performing id call to peach-network microservice.");
+    let id = client
+        .id(&iface, &ssid)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing disable call to peach-network microservice.");
+    client
+        .disable(&id, &iface)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
 
     let response = "success".to_string();
 
     Ok(response)
 }
 
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `disconnect` method, which drops the current association of the interface.
+/// The saved credentials are kept (unlike `forget`), so the network can be
+/// reconnected later.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn disconnect(iface: &str) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
+
+    let response = client
+        .disconnect(iface)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `get_country` method, returning the WiFi country code (e.g. `GB`) set in
+/// `wpa_supplicant.conf`, or `Ok(None)` if no country is set.
+pub fn get_country_code() -> std::result::Result<Option<String>, PeachError> {
+    let mut client = network_client()?;
+
+    let code = optional(client.get_country().call())?;
+
+    Ok(code.map(|code| code.trim().to_string()))
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `set_country` method, which sets the WiFi country code in
+/// `wpa_supplicant.conf`. The country determines the channels and transmit
+/// power which may be used.
+///
+/// The code must be two letters (an ISO 3166-1 alpha-2 code, e.g. `gb` or
+/// `DE`) and is sent in uppercase. Any other code returns
+/// `PeachError::InvalidCountryCode` without calling the microservice.
+///
+/// # Arguments
+///
+/// * `code` - A string slice containing a two-letter country code.
+pub fn set_country_code(code: &str) -> std::result::Result<String, PeachError> {
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(PeachError::InvalidCountryCode {
+            code: code.to_string(),
+        });
+    }
+    config_manager::ensure_not_in_maintenance_mode()?;
+
+    let mut client = network_client()?;
+
+    let response = client
+        .set_country(&code.to_ascii_uppercase())
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `id`, `delete` and `save` methods.
 ///
@@ -183,25 +456,31 @@ pub fn disable(iface: &str, ssid: &str) -> std::result::Result<String, PeachErro
 /// * `iface` - A string slice containing the network interface identifier.
 /// * `ssid` - A string slice containing the SSID of a network.
 pub fn forget(iface: &str, ssid: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
-
-    info!("Performing id call to peach-network microservice.");
-    let id = client.id(&iface, &ssid).call()?;
-    info!("Performing delete call to peach-network microservice.");
-    // WEIRD BUG: the parameters below are technically in the wrong order:
-    // it should be id first and then iface, but somehow they get twisted.
-    // i don't understand computers.
-    client.delete(&iface, &id).call()?;
-    info!("Performing save call to peach-network microservice.");
-    client.save().call()?;
+    let mut client = network_client()?;
+
+    forget_with(&mut client, iface, ssid)
+}
+
+/// Same as `forget`, but makes the calls with the given client.
+pub fn forget_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+
+    info!(target: LOG_TARGET, "Performing id call to peach-network microservice.");
+    let id = client
+        .id(&iface, &ssid)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing delete call to peach-network microservice.");
+    client
+        .delete(iface, &id)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing save call to peach-network microservice.");
+    client.save().call().map_err(rpc_error(SERVICE))?;
 
     let response = "success".to_string();
 
@@ -216,21 +495,49 @@ pub fn forget(iface: &str, ssid: &str) -> std::result::Result<String, PeachError
 /// * `iface` - A string slice containing the network interface identifier.
 /// * `ssid` - A string slice containing the SSID of a network.
 pub fn id(iface: &str, ssid: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.id(iface, ssid).call()?;
+    let response = client.id(iface, ssid).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Parts of the messages of the server errors with which `peach-network`
+/// reports that a value is not available, rather than that the call failed,
+/// e.g. "Failed to retrieve SSID for wlan0. Interface may not be connected".
+const NOT_AVAILABLE_MESSAGES: &[&str] = &[
+    "may not be connected",
+    "may not exist",
+    "no saved networks found",
+    "not available",
+];
+
+/// Converts the response of a call for a value which may be absent into an
+/// `Option`. `peach-network` reports a missing value (such as the SSID of an
+/// interface which is not associated) with an empty response or a server error
+/// with one of the `NOT_AVAILABLE_MESSAGES`, which become `None`. Transport
+/// errors and other JSON-RPC errors are returned as errors.
+fn optional(
+    response: std::result::Result<String, jsonrpc_client_core::Error>,
+) -> std::result::Result<Option<String>, PeachError> {
+    match response {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(err) => match err.kind() {
+            jsonrpc_client_core::ErrorKind::JsonRpcError(json_err)
+                if matches!(json_err.code, jsonrpc_core::ErrorCode::ServerError(_))
+                    && NOT_AVAILABLE_MESSAGES
+                        .iter()
+                        .any(|msg| json_err.message.to_lowercase().contains(msg)) =>
+            {
+                debug!(target: LOG_TARGET, "No value available: {}", json_err.message);
+                Ok(None)
+            }
+            _ => Err(rpc_error(SERVICE)(err)),
+        },
+    }
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `ip` method.
 ///
@@ -238,17 +545,174 @@ pub fn id(iface: &str, ssid: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn ip(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.ip(iface).call()?;
+    let response = client.ip(iface).call().map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
+/// Like `ip`, but returns `Ok(None)` rather than an error when the interface
+/// has no IP address, e.g. because it is not connected. Transport errors are
+/// still returned as errors.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn ip_opt(iface: &str) -> std::result::Result<Option<String>, PeachError> {
+    let mut client = network_client()?;
+
+    optional(client.ip(iface).call())
+}
+
+/// Like `ip_opt`, but parses the address into an `IpAddr` (IPv4 or IPv6).
+/// Returns `Ok(None)` when the interface has no IP address, and
+/// `PeachError::InvalidIpAddress` when the response is not a valid address.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn ip_addr(iface: &str) -> std::result::Result<Option<IpAddr>, PeachError> {
+    match ip_opt(iface)? {
+        Some(ip) => {
+            let ip = ip.trim();
+            let addr = ip.parse().context(InvalidIpAddress {
+                iface: iface.to_string(),
+                value: ip.to_string(),
+            })?;
+            Ok(Some(addr))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Directory listing the network interfaces of the device, used when the
+/// `peach-network` microservice does not support `list_interfaces`.
+pub const SYSFS_NET_PATH: &str = "/sys/class/net";
+
+/// Returns the directory listing the network interfaces of the device, which
+/// can be changed with the `PEACH_SYSFS_NET_PATH` environment variable.
+pub fn sysfs_net_path() -> String {
+    env::var("PEACH_SYSFS_NET_PATH").unwrap_or_else(|_| SYSFS_NET_PATH.to_string())
+}
+
+/// Returns the names of the network interfaces on the device (e.g. `eth0`,
+/// `wlan0`), sorted by name, e.g. to let the user choose an interface rather
+/// than assuming `wlan0`.
+///
+/// The `peach-network` `list_interfaces` method is used if the microservice
+/// supports it. Otherwise (or if the microservice cannot be reached), each
+/// entry of `/sys/class/net` (see `sysfs_net_path`) is an interface.
+pub fn list_interfaces() -> std::result::Result<Vec<String>, PeachError> {
+    let mut client = network_client()?;
+
+    list_interfaces_with(&mut client)
+}
+
+/// Same as `list_interfaces`, but makes the call with the given client.
+pub fn list_interfaces_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+) -> std::result::Result<Vec<String>, PeachError> {
+    let mut interfaces: Vec<String> = match client.list_interfaces().call() {
+        Ok(response) => serde_json::from_str(&response)?,
+        Err(err) => {
+            let path = sysfs_net_path();
+            debug!(
+                target: LOG_TARGET,
+                "list_interfaces call failed, reading {} instead: {}", path, err
+            );
+            let entries = std::fs::read_dir(&path).context(StdIoError {
+                msg: format!("Failed to read {}", path),
+            })?;
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        }
+    };
+    interfaces.sort();
+
+    Ok(interfaces)
+}
+
+/// Returns the MAC address of the given interface, e.g. `b8:27:eb:12:34:56`,
+/// read from `/sys/class/net/<iface>/address` (see `sysfs_net_path`). The
+/// address is lowercase and colon-separated. Returns
+/// `PeachError::InterfaceNotFound` if the interface does not exist.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn mac_address(iface: &str) -> std::result::Result<String, PeachError> {
+    let iface_path = Path::new(&sysfs_net_path()).join(iface);
+    if iface.is_empty() || iface.contains('/') || !iface_path.exists() {
+        return Err(PeachError::InterfaceNotFound {
+            iface: iface.to_string(),
+        });
+    }
+    let address_path = iface_path.join("address");
+    let address = std::fs::read_to_string(&address_path).context(StdIoError {
+        msg: format!("Failed to read {}", address_path.display()),
+    })?;
+
+    Ok(address.trim().to_lowercase())
+}
+
+/// Helper function which returns the state of every network interface on the
+/// device, keyed by interface name. A single client is used for all of the
+/// calls. Interfaces whose state cannot be retrieved are given the state
+/// `"unknown"` rather than being left out.
+pub fn all_states() -> std::result::Result<HashMap<String, String>, PeachError> {
+    let mut client = network_client()?;
+    let interfaces = list_interfaces_with(&mut client)?;
+
+    let states = interfaces
+        .into_iter()
+        .map(|iface| {
+            let state = client.state(&iface).call().unwrap_or_else(|err| {
+                debug!(target: LOG_TARGET, "Failed to get state of {}: {}", iface, err);
+                "unknown".to_string()
+            });
+            (iface, state)
+        })
+        .collect();
+
+    Ok(states)
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `id` and `modify` methods, which set a new password for a saved network in
+/// place. Unlike `update`, the network keeps its position (and so its priority)
+/// in `wpa_supplicant.conf`, and there is no window in which the credentials
+/// have been deleted but not yet re-added.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+/// * `ssid` - A string slice containing the SSID of a network.
+/// * `pass` - A string slice containing the new password for the network.
+pub fn modify(iface: &str, ssid: &str, pass: &str) -> std::result::Result<String, PeachError> {
+    let mut client = network_client()?;
+
+    modify_with(&mut client, iface, ssid, pass)
+}
+
+/// Same as `modify`, but makes the calls with the given client.
+pub fn modify_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+
+    info!(target: LOG_TARGET, "Performing id call to peach-network microservice.");
+    let id = client.id(iface, ssid).call().map_err(rpc_error(SERVICE))?;
+    info!(target: LOG_TARGET, "Performing modify call to peach-network microservice.");
+    let response = client
+        .modify(&id, iface, pass)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -257,17 +721,52 @@ pub fn ip(iface: &str) -> std::result::Result<String, PeachError> {
 /// `ping` method, which serves as a means of determining availability of the
 /// microservice (ie. there will be no response if `peach-network` is not
 /// running).
+///
+/// The call is retried if `peach-network` is not running, when
+/// `PEACH_RPC_RETRIES` is set (see `retry::with_retries`).
 pub fn ping() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
-    let response = client.ping().call()?;
+    ping_at(&ServerAddresses::from_env().network)
+}
+
+/// Same as `ping`, but calls the `peach-network` microservice at the given
+/// address (`host:port`) rather than the one in `PEACH_NETWORK_SERVER`.
+pub fn ping_at(addr: &str) -> std::result::Result<String, PeachError> {
+    let mut client = network_client_at(addr)?;
+
+    with_retries(rpc_retries().saturating_add(1), || {
+        client.ping().call().map_err(rpc_error(SERVICE))
+    })
+}
+
+/// Returns true if `peach-network` responds to a `ping`. Errors are treated as
+/// the microservice being offline.
+pub fn is_network_online() -> bool {
+    match network_client() {
+        Ok(mut client) => client.ping().call().is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Calls the `peach-network` `ping` method and returns the time taken for the
+/// response to arrive, e.g. to show the responsiveness of the microservice on
+/// a status page. The call is not retried, and setting up the transport is
+/// not included in the measured time.
+pub fn ping_with_latency() -> std::result::Result<Duration, PeachError> {
+    let mut client = network_client()?;
+
+    let start = Instant::now();
+    client.ping().call().map_err(rpc_error(SERVICE))?;
+    let latency = start.elapsed();
+    debug!(target: LOG_TARGET, "Pinged peach-network in {:?}.", latency);
+
+    Ok(latency)
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `version` method, returning the version of the running microservice.
+pub fn version() -> std::result::Result<String, PeachError> {
+    let mut client = network_client()?;
+    let response = client.version().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -275,21 +774,38 @@ pub fn ping() -> std::result::Result<String, PeachError> {
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `reconfigure` method.
 pub fn reconfigure() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
 
-    let response = client.reconfigure().call()?;
+    let response = client.reconfigure().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `reconnect` method, which makes the interface re-associate with an access
+/// point, e.g. after changing the country setting or when the link is flaky.
+///
+/// Unlike `reconfigure`, the `wpa_supplicant` config file is not reread, so
+/// this is quicker when the saved networks have not changed. Unlike `connect`,
+/// no particular network is selected: the interface associates with the best
+/// of its enabled networks.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+///
+/// The call is retried if `peach-network` is not running, when
+/// `PEACH_RPC_RETRIES` is set (see `retry::with_retries`).
+pub fn reconnect(iface: &str) -> std::result::Result<String, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut client = network_client()?;
+
+    with_retries(rpc_retries().saturating_add(1), || {
+        client.reconnect(iface).call().map_err(rpc_error(SERVICE))
+    })
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `rssi` method.
 ///
@@ -297,21 +813,53 @@ pub fn reconfigure() -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn rssi(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.rssi(iface).call()?;
+    let response = client.rssi(iface).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Like `rssi`, but returns `Ok(None)` rather than an error when the interface
+/// has no signal strength, e.g. because it is not associated with an access
+/// point. Transport errors are still returned as errors.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn rssi_opt(iface: &str) -> std::result::Result<Option<String>, PeachError> {
+    let mut client = network_client()?;
+
+    optional(client.rssi(iface).call())
+}
+
+/// Parses a signal strength returned by `peach-network` for the given
+/// interface, returning `PeachError::InvalidSignalStrength` if it is not a
+/// whole number.
+fn parse_signal(iface: &str, value: &str) -> std::result::Result<i32, PeachError> {
+    let value = value.trim();
+    let signal = value.parse().context(InvalidSignalStrength {
+        iface: iface.to_string(),
+        value: value.to_string(),
+    })?;
+
+    Ok(signal)
+}
+
+/// Returns the average signal strength of the interface in dBm, or `Ok(None)`
+/// when no signal is available, e.g. because the interface is not associated
+/// with an access point.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn rssi_dbm(iface: &str) -> std::result::Result<Option<i32>, PeachError> {
+    match rssi_opt(iface)? {
+        Some(rssi) => Ok(Some(parse_signal(iface, &rssi)?)),
+        None => Ok(None),
+    }
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `rssi_percent` method.
 ///
@@ -319,51 +867,60 @@ pub fn rssi(iface: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn rssi_percent(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.rssi_percent(iface).call()?;
+    let response = client
+        .rssi_percent(iface)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Returns the average signal quality of the interface as a percentage,
+/// clamped to `0..=100`, or `Ok(None)` when no signal is available, e.g.
+/// because the interface is not associated with an access point.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn rssi_percentage(iface: &str) -> std::result::Result<Option<u8>, PeachError> {
+    let mut client = network_client()?;
+
+    match optional(client.rssi_percent(iface).call())? {
+        Some(percent) => {
+            let percent = parse_signal(iface, &percent)?.clamp(0, 100);
+            Ok(Some(percent as u8))
+        }
+        None => Ok(None),
+    }
+}
+
 /// Helper function to determine if a given SSID already exists in the
 /// `wpa_supplicant.conf` file, indicating that network credentials have already
 /// been added for that access point. Creates a JSON-RPC client with http
 /// transport and calls the `peach-network` `saved_networks` method. Returns a
 /// boolean expression inside a Result type.
 ///
+/// If `peach-network` cannot be reached, the error is returned rather than the
+/// network being reported as not saved, so that `add` does not add a duplicate.
+///
 /// # Arguments
 ///
 /// * `ssid` - A string slice containing the SSID of a network.
 pub fn saved_ap(ssid: &str) -> std::result::Result<bool, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
+
+    saved_ap_with(&mut client, ssid)
+}
 
+/// Same as `saved_ap`, but makes the call with the given client.
+pub fn saved_ap_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    ssid: &str,
+) -> std::result::Result<bool, PeachError> {
     // retrieve a list of access points with saved credentials
-    let saved_aps = match client.saved_networks().call() {
-        Ok(ssids) => {
-            let networks: Vec<Networks> = serde_json::from_str(ssids.as_str())
-                .expect("Failed to deserialize saved_networks response");
-            networks
-        }
-        // return an empty vector if there are no saved access point credentials
-        Err(_) => Vec::new(),
-    };
+    let saved_aps = saved_networks_parsed_with(client)?;
 
     // loop through the access points in the list
     for network in saved_aps {
@@ -381,20 +938,44 @@ pub fn saved_ap(ssid: &str) -> std::result::Result<bool, PeachError> {
 /// `saved_networks` method, which returns a list of networks saved in
 /// `wpa_supplicant.conf`.
 pub fn saved_networks() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
-    let response = client.saved_networks().call()?;
+    let mut client = network_client()?;
+    let response = client.saved_networks().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Parses a `saved_networks` response, in which an empty response means that
+/// no networks are saved.
+fn parse_saved_networks(response: &str) -> std::result::Result<Vec<Networks>, PeachError> {
+    if response.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let networks: Vec<Networks> = serde_json::from_str(response)?;
+
+    Ok(networks)
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-network`
+/// `saved_networks` method, returning the saved networks parsed into
+/// `Networks` structs. The networks are in the order they appear in
+/// `wpa_supplicant.conf`, which is the order of priority when several are in
+/// range. Malformed data returns `PeachError::Serde`.
+pub fn saved_networks_parsed() -> std::result::Result<Vec<Networks>, PeachError> {
+    let mut client = network_client()?;
+
+    saved_networks_parsed_with(&mut client)
+}
+
+/// Same as `saved_networks_parsed`, but makes the call with the given client.
+pub fn saved_networks_parsed_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+) -> std::result::Result<Vec<Networks>, PeachError> {
+    match optional(client.saved_networks().call())? {
+        Some(response) => parse_saved_networks(&response),
+        None => Ok(Vec::new()),
+    }
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `ssid` method.
 ///
@@ -402,21 +983,50 @@ pub fn saved_networks() -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn ssid(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.ssid(iface).call()?;
+    let response = client.ssid(iface).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// Like `ssid`, but returns `Ok(None)` rather than an error when the interface
+/// has no SSID, e.g. because it is not associated with an access point.
+/// Transport errors are still returned as errors.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn ssid_opt(iface: &str) -> std::result::Result<Option<String>, PeachError> {
+    let mut client = network_client()?;
+
+    optional(client.ssid(iface).call())
+}
+
+/// Helper function to determine if the given SSID is the network currently
+/// associated with the interface, e.g. to mark the active entry in a list of
+/// saved networks. Calls the `peach-network` `ssid` method.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+/// * `ssid` - A string slice containing the SSID of a network.
+/// * `case_insensitive` - Whether to ignore ASCII case when comparing SSIDs.
+pub fn active_ssid_matches(
+    iface: &str,
+    ssid: &str,
+    case_insensitive: bool,
+) -> std::result::Result<bool, PeachError> {
+    let active_ssid = self::ssid(iface)?;
+    let matches = if case_insensitive {
+        active_ssid.eq_ignore_ascii_case(ssid)
+    } else {
+        active_ssid == ssid
+    };
+
+    Ok(matches)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `state` method.
 ///
@@ -424,21 +1034,127 @@ pub fn ssid(iface: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn state(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.state(iface).call()?;
+    let response = client.state(iface).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
 
+/// The operational state of a network interface, as reported by the
+/// `peach-network` `state` method.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum ConnectionState {
+    Up,
+    Down,
+    Dormant,
+    /// Any other state, such as `unknown` or `notpresent`, holding the raw value.
+    Unknown(String),
+}
+
+impl From<&str> for ConnectionState {
+    fn from(state: &str) -> ConnectionState {
+        match state.trim().to_lowercase().as_str() {
+            "up" => ConnectionState::Up,
+            "down" => ConnectionState::Down,
+            "dormant" => ConnectionState::Dormant,
+            _ => ConnectionState::Unknown(state.trim().to_string()),
+        }
+    }
+}
+
+/// Calls the `peach-network` `state` method and returns the state of the
+/// interface as a `ConnectionState`.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+pub fn connection_state(iface: &str) -> std::result::Result<ConnectionState, PeachError> {
+    let state = state(iface)?;
+
+    Ok(ConnectionState::from(state.as_str()))
+}
+
+/// Returns the payload for a QR code which joins the given network when
+/// scanned, in the `WIFI:` format understood by phone cameras, e.g. to let
+/// users join the device's access point during onboarding. The characters
+/// `\`, `;`, `,`, `:` and `"` in the SSID and password are escaped with a
+/// backslash. A network without a password is given the type `nopass`.
+///
+/// # Example
+///
+/// ```
+/// use peach_lib::network_client::wifi_qr_payload;
+///
+/// assert_eq!(
+///     wifi_qr_payload("peach", "cloudy", false),
+///     "WIFI:T:WPA;S:peach;P:cloudy;H:false;;"
+/// );
+/// assert_eq!(
+///     wifi_qr_payload("a;b", r"c:d,e\f", true),
+///     r"WIFI:T:WPA;S:a\;b;P:c\:d\,e\\f;H:true;;"
+/// );
+/// assert_eq!(wifi_qr_payload("open", "", false), "WIFI:T:nopass;S:open;H:false;;");
+/// ```
+pub fn wifi_qr_payload(ssid: &str, pass: &str, hidden: bool) -> String {
+    let escape = |value: &str| {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+
+    if pass.is_empty() {
+        format!("WIFI:T:nopass;S:{};H:{};;", escape(ssid), hidden)
+    } else {
+        format!(
+            "WIFI:T:WPA;S:{};P:{};H:{};;",
+            escape(ssid),
+            escape(pass),
+            hidden
+        )
+    }
+}
+
+/// The access point interface, which is up while the device is in AP mode.
+const AP_IFACE: &str = "ap0";
+
+/// Whether the device is serving its own access point or connecting to other
+/// networks as a client, switched with `activate_ap` and `activate_client`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum WifiMode {
+    AccessPoint,
+    Client,
+}
+
+impl WifiMode {
+    /// Returns the mode for the given state of the `ap0` interface, which is
+    /// `None` if the interface does not exist. The device is in AP mode only
+    /// while `ap0` is up; in client mode `ap0` is down or removed, and `wlan0`
+    /// may be in any state (e.g. down when there is no network in range).
+    pub fn from_ap_state(ap_state: Option<&ConnectionState>) -> WifiMode {
+        match ap_state {
+            Some(ConnectionState::Up) => WifiMode::AccessPoint,
+            _ => WifiMode::Client,
+        }
+    }
+}
+
+/// Returns whether the device is currently in AP mode or client mode, inferred
+/// from the state of the `ap0` interface (see `WifiMode::from_ap_state`).
+pub fn current_mode() -> std::result::Result<WifiMode, PeachError> {
+    let mut client = network_client()?;
+
+    let ap_state =
+        optional(client.state(AP_IFACE).call())?.map(|state| ConnectionState::from(state.as_str()));
+
+    Ok(WifiMode::from_ap_state(ap_state.as_ref()))
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
 /// `status` method.
 ///
@@ -446,17 +1162,9 @@ pub fn state(iface: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn status(iface: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.status(iface).call()?;
+    let response = client.status(iface).call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -468,64 +1176,268 @@ pub fn status(iface: &str) -> std::result::Result<String, PeachError> {
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 pub fn traffic(iface: &str) -> std::result::Result<Traffic, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+    let mut client = network_client()?;
 
-    let response = client.traffic(iface).call()?;
-    let t: Traffic = serde_json::from_str(&response).unwrap();
+    traffic_with(&mut client, iface)
+}
+
+/// Same as `traffic`, but makes the call with the given client.
+pub fn traffic_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+) -> std::result::Result<Traffic, PeachError> {
+    let response = client.traffic(iface).call().map_err(rpc_error(SERVICE))?;
+    // an interface which has not yet carried any traffic has no counters
+    if response.trim().is_empty() || response.trim() == "null" {
+        return Err(PeachError::NoTrafficData {
+            iface: iface.to_string(),
+        });
+    }
+    let t: Traffic = serde_json::from_str(&response)?;
 
     Ok(t)
 }
 
+/// Helper function which returns the combined traffic of the given interfaces,
+/// by calling the `peach-network` `traffic` method for each of them with a
+/// single client and summing the `received` and `transmitted` counters.
+///
+/// The counters are cumulative since the interfaces came up (normally at boot)
+/// and reset when the device restarts, so the total is not a rate. The units
+/// of the total are only set if every interface reports the same units.
+///
+/// # Arguments
+///
+/// * `ifaces` - The network interface identifiers to sum the traffic of.
+/// * `skip_errors` - Whether to leave out interfaces whose traffic cannot be
+///   retrieved (e.g. because they do not exist) rather than returning the error.
+///   Errors reaching `peach-network` itself (see `PeachError::is_transport_error`)
+///   are always returned, since they would leave out every interface.
+pub fn total_traffic(
+    ifaces: &[&str],
+    skip_errors: bool,
+) -> std::result::Result<Traffic, PeachError> {
+    let mut client = network_client()?;
+
+    let mut traffic = Vec::new();
+    for iface in ifaces {
+        match traffic_with(&mut client, iface) {
+            Ok(t) => traffic.push(t),
+            Err(err) if skip_errors && !err.is_transport_error() => {
+                debug!(target: LOG_TARGET, "Skipping traffic for {}: {}", iface, err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Traffic::sum(&traffic.iter().collect::<Vec<_>>()))
+}
+
+/// The steps of `update` which completed successfully.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct UpdateReport {
+    /// The old credentials were deleted.
+    pub deleted: bool,
+    /// The deletion was saved to `wpa_supplicant.conf`.
+    pub saved: bool,
+    /// The new credentials were added.
+    pub added: bool,
+    /// `wpa_supplicant` was reconfigured with the new credentials.
+    pub reconfigured: bool,
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-network`
-/// `id`, `delete`, `save` and `add` methods. These combined calls allow the
-/// saved password for an access point to be updated.
+/// `id`, `delete`, `save`, `add` and `reconfigure` methods. These combined calls
+/// allow the saved password for an access point to be updated.
+///
+/// Returns an `UpdateReport` of the steps performed. If a step fails, the
+/// sequence stops and `PeachError::NetworkUpdateFailed` is returned, naming the
+/// failed step and carrying a report of the steps which completed before it, so
+/// that the caller can tell what state the saved credentials were left in.
 ///
 /// # Arguments
 ///
 /// * `iface` - A string slice containing the network interface identifier.
 /// * `ssid` - A string slice containing the SSID of a network.
 /// * `pass` - A string slice containing the password for a network.
-pub fn update(iface: &str, ssid: &str, pass: &str) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for network client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr =
-        env::var("PEACH_NETWORK_SERVER").unwrap_or_else(|_| "127.0.0.1:5110".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_network service.");
-    let mut client = PeachNetworkClient::new(transport_handle);
+pub fn update(
+    iface: &str,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<UpdateReport, PeachError> {
+    let mut client = network_client()?;
+
+    update_with(&mut client, iface, ssid, pass)
+}
+
+/// Same as `update`, but makes the calls with the given client.
+pub fn update_with(
+    client: &mut PeachNetworkClient<HttpHandle>,
+    iface: &str,
+    ssid: &str,
+    pass: &str,
+) -> std::result::Result<UpdateReport, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    let mut report = UpdateReport::default();
+    let failed_at = |step: &str, report: &UpdateReport| {
+        let step = step.to_string();
+        let report = report.clone();
+        move |err: PeachError| PeachError::NetworkUpdateFailed {
+            step,
+            report,
+            source: Box::new(err),
+        }
+    };
 
     // get the id of the network
-    info!("Performing id call to peach-network microservice.");
-    let id = client.id(&iface, &ssid).call()?;
+    info!(target: LOG_TARGET, "Performing id call to peach-network microservice.");
+    let id = client
+        .id(&iface, &ssid)
+        .call()
+        .map_err(rpc_error(SERVICE))
+        .map_err(failed_at("id", &report))?;
     // delete the old credentials
-    // WEIRD BUG: the parameters below are technically in the wrong order:
-    // it should be id first and then iface, but somehow they get twisted.
-    // i don't understand computers.
-    info!("Performing delete call to peach-network microservice.");
-    client.delete(&iface, &id).call()?;
+    info!(target: LOG_TARGET, "Performing delete call to peach-network microservice.");
+    client
+        .delete(iface, &id)
+        .call()
+        .map_err(rpc_error(SERVICE))
+        .map_err(failed_at("delete", &report))?;
+    report.deleted = true;
     // save the updates to wpa_supplicant.conf
-    info!("Performing save call to peach-network microservice.");
-    client.save().call()?;
+    info!(target: LOG_TARGET, "Performing save call to peach-network microservice.");
+    client
+        .save()
+        .call()
+        .map_err(rpc_error(SERVICE))
+        .map_err(failed_at("save", &report))?;
+    report.saved = true;
     // add the new credentials
-    info!("Performing add call to peach-network microservice.");
-    client.add(ssid, pass).call()?;
+    info!(target: LOG_TARGET, "Performing add call to peach-network microservice.");
+    client
+        .add(ssid, pass)
+        .call()
+        .map_err(rpc_error(SERVICE))
+        .map_err(failed_at("add", &report))?;
+    report.added = true;
     // reconfigure wpa_supplicant with latest addition to config
-    info!("Performing reconfigure call to peach-network microservice.");
-    client.reconfigure().call()?;
+    info!(target: LOG_TARGET, "Performing reconfigure call to peach-network microservice.");
+    client
+        .reconfigure()
+        .call()
+        .map_err(rpc_error(SERVICE))
+        .map_err(failed_at("reconfigure", &report))?;
+    report.reconfigured = true;
+
+    Ok(report)
+}
 
-    let response = "success".to_string();
+/// A difference between the behaviour of the `peach-network` microservice and
+/// what this client expects, found by `verify_rpc_contract`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ContractIssue {
+    /// The RPC method which did not behave as expected.
+    pub method: String,
+    /// What went wrong, including the error returned by the server, if any.
+    pub description: String,
+}
 
-    Ok(response)
+/// Checks that the `peach-network` `id`, `delete` and `save` methods behave the
+/// way `forget` and `update` rely on, by adding a scratch network, looking it
+/// up and deleting it again. In particular, this detects whether the server
+/// still expects the `delete` parameters with the interface before the id (as
+/// declared in `PeachNetworkClient`), so that a server update which changes it
+/// is noticed.
+///
+/// This modifies `wpa_supplicant.conf`, so it must be given the SSID of a test
+/// network which is not already saved; it refuses to run otherwise. Any issues
+/// found are returned, and an empty list means the contract holds. The scratch
+/// network is always deleted if it can be found, and the config is only saved
+/// once it has been deleted.
+///
+/// # Arguments
+///
+/// * `iface` - A string slice containing the network interface identifier.
+/// * `test_ssid` - A string slice containing the SSID of an unsaved test network.
+pub fn verify_rpc_contract(
+    iface: &str,
+    test_ssid: &str,
+) -> std::result::Result<Vec<ContractIssue>, PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    if test_ssid.is_empty() || saved_ap(test_ssid)? {
+        return Err(PeachError::ContractTestNetworkRequired {
+            ssid: test_ssid.to_string(),
+        });
+    }
+    let mut client = network_client()?;
+    let mut issues = Vec::new();
+    let mut issue = |method: &str, description: String| {
+        warn!(target: LOG_TARGET, "RPC contract issue with {}: {}", method, description);
+        issues.push(ContractIssue {
+            method: method.to_string(),
+            description,
+        });
+    };
+
+    info!(target: LOG_TARGET, "Adding scratch network {} to check the RPC contract.", test_ssid);
+    client
+        .add(test_ssid, "peach-contract-check")
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    let id = match client.id(iface, test_ssid).call() {
+        Ok(id) => Some(id),
+        Err(err) => {
+            issue(
+                "id",
+                format!("failed to find the network just added: {}", err),
+            );
+            None
+        }
+    };
+
+    // the scratch network is removed whatever the outcome of the checks above
+    let deleted = match &id {
+        Some(id) => match client.delete(iface, id).call() {
+            Ok(_) => true,
+            // retry with the arguments swapped to tell a changed signature from a failed delete
+            Err(err) => match client.delete(id, iface).call() {
+                Ok(_) => {
+                    issue(
+                        "delete",
+                        "the server now expects the id before the interface, so the client signature must be swapped".to_string(),
+                    );
+                    true
+                }
+                Err(_) => {
+                    issue(
+                        "delete",
+                        format!("failed with either argument order: {}", err),
+                    );
+                    false
+                }
+            },
+        },
+        None => false,
+    };
+    if !deleted {
+        // saving now would persist the scratch network in wpa_supplicant.conf
+        warn!(target: LOG_TARGET,
+            "Scratch network {} could not be removed and was not saved; remove it before saving",
+            test_ssid
+        );
+        return Ok(issues);
+    }
+    if let Err(err) = client.save().call() {
+        issue("save", format!("failed to save the config: {}", err));
+    }
+    if client.id(iface, test_ssid).call().is_ok() && saved_ap_with(&mut client, test_ssid)? {
+        issue(
+            "delete",
+            format!("the scratch network {} was not removed", test_ssid),
+        );
+    }
+
+    Ok(issues)
 }
 
 jsonrpc_client!(pub struct PeachNetworkClient {
@@ -545,13 +1457,17 @@ jsonrpc_client!(pub struct PeachNetworkClient {
     pub fn connect(&mut self, id: &str, iface: &str) -> RpcRequest<String>;
 
     /// JSON-RPC request to delete the credentials for the given network from the wpa_supplicant config.
-    pub fn delete(&mut self, id: &str, iface: &str) -> RpcRequest<String>;
+    /// Unlike `connect` and `disable`, `peach-network` expects the interface before the id.
+    pub fn delete(&mut self, iface: &str, id: &str) -> RpcRequest<String>;
 
     /// JSON-RPC request to disable the network for the given interface and ID.
     pub fn disable(&mut self, id: &str, iface: &str) -> RpcRequest<String>;
 
     /// JSON-RPC request to disconnect the network for the given interface.
-    //pub fn disconnect(&mut self, iface: &str) -> RpcRequest<String>;
+    pub fn disconnect(&mut self, iface: &str) -> RpcRequest<String>;
+
+    /// JSON-RPC request to get the WiFi country code.
+    pub fn get_country(&mut self) -> RpcRequest<String>;
 
     /// JSON-RPC request to get the ID for the given interface and SSID.
     pub fn id(&mut self, iface: &str, ssid: &str) -> RpcRequest<String>;
@@ -559,17 +1475,23 @@ jsonrpc_client!(pub struct PeachNetworkClient {
     /// JSON-RPC request to get the IP address for the given interface.
     pub fn ip(&mut self, iface: &str) -> RpcRequest<String>;
 
+    /// JSON-RPC request to list the network interfaces of the device.
+    pub fn list_interfaces(&mut self) -> RpcRequest<String>;
+
     /// JSON-RPC request to set a new network password for the given interface and ID.
-    //pub fn modify(&mut self, id: &str, iface: &str, pass: &str) -> RpcRequest<String>;
+    pub fn modify(&mut self, id: &str, iface: &str, pass: &str) -> RpcRequest<String>;
 
     /// JSON-RPC request to check peach-network availability.
     pub fn ping(&mut self) -> RpcRequest<String>;
 
+    /// JSON-RPC request to get the version of the `peach-network` microservice.
+    pub fn version(&mut self) -> RpcRequest<String>;
+
     /// JSON-RPC request to reread the wpa_supplicant config for the given interface.
     pub fn reconfigure(&mut self) -> RpcRequest<String>;
 
     /// JSON-RPC request to reconnect WiFi for the given interface.
-    //pub fn reconnect(&mut self, iface: &str) -> RpcRequest<String>;
+    pub fn reconnect(&mut self, iface: &str) -> RpcRequest<String>;
 
     /// JSON-RPC request to get the average signal strength (dBm) for the given interface.
     pub fn rssi(&mut self, iface: &str) -> RpcRequest<String>;
@@ -583,6 +1505,9 @@ jsonrpc_client!(pub struct PeachNetworkClient {
     /// JSON-RPC request to list all networks saved in `wpa_supplicant.conf`.
     pub fn saved_networks(&mut self) -> RpcRequest<String>;
 
+    /// JSON-RPC request to set the WiFi country code.
+    pub fn set_country(&mut self, code: &str) -> RpcRequest<String>;
+
     /// JSON-RPC request to get the SSID of the currently-connected network for the given interface.
     pub fn ssid(&mut self, iface: &str) -> RpcRequest<String>;
 
@@ -1,25 +1,80 @@
 use std::env;
+use std::time::Duration;
 
 use jsonrpc_client_core::{expand_params, jsonrpc_client};
-use jsonrpc_client_http::HttpTransport;
+use jsonrpc_client_http::HttpHandle;
 use log::{debug, info};
+#[cfg(feature = "qr")]
+use qrcode::{Color, EcLevel, QrCode};
 
-use crate::error::PeachError;
+use crate::error::{rpc_error, PeachError};
+use crate::server_addresses::ServerAddresses;
+use crate::transport::http_transport;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::oled=debug`.
+const LOG_TARGET: &str = "peach_lib::oled";
+
+/// The name of the microservice called by this client, used in error messages.
+const SERVICE: &str = "peach-oled";
+
+/// Returns the timeout for `peach-oled` requests, read (in milliseconds) from
+/// the `PEACH_OLED_TIMEOUT_MS` environment variable. The shared
+/// `PEACH_RPC_TIMEOUT_MS` timeout is used when the variable is not set.
+fn default_timeout() -> Option<Duration> {
+    env::var("PEACH_OLED_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+}
+
+/// Returns true when the `PEACH_OLED_NOOP` environment variable is set to `1`
+/// or `true`. In no-op mode every call in this module returns `Ok` without
+/// connecting to `peach-oled`, for development machines without a display.
+fn noop_mode() -> bool {
+    matches!(env::var("PEACH_OLED_NOOP").as_deref(), Ok("1") | Ok("true"))
+}
+
+/// Timeout for the ping made by `is_available`.
+const AVAILABILITY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Creates a JSON-RPC client with http transport for the `peach-oled`
+/// microservice. Calls which take longer than the given timeout (or
+/// `transport::rpc_timeout()` if none is given) return `PeachError::Timeout`.
+fn oled_client(
+    timeout: Option<Duration>,
+) -> std::result::Result<PeachOledClient<HttpHandle>, PeachError> {
+    oled_client_with(&ServerAddresses::from_env(), timeout)
+}
+
+/// Creates a JSON-RPC client with http transport for the `peach-oled`
+/// microservice at the address given in `addresses`. Calls which take longer
+/// than the given timeout (or `transport::rpc_timeout()` if none is given)
+/// return `PeachError::Timeout`.
+pub fn oled_client_with(
+    addresses: &ServerAddresses,
+    timeout: Option<Duration>,
+) -> std::result::Result<PeachOledClient<HttpHandle>, PeachError> {
+    debug!(target: LOG_TARGET, "Creating HTTP transport for OLED client.");
+    let transport = http_transport(timeout)?;
+    let http_server = format!("http://{}", addresses.oled);
+    debug!(target: LOG_TARGET, "Creating HTTP transport handle on {}.", http_server);
+    let transport_handle = transport.handle(&http_server)?;
+    info!(target: LOG_TARGET, "Creating client for peach_oled service.");
+
+    Ok(PeachOledClient::new(transport_handle))
+}
 
 /// Creates a JSON-RPC client with http transport and calls the `peach-oled`
 /// `clear` method.
 pub fn clear() -> std::result::Result<(), PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok(());
+    }
 
-    client.clear().call()?;
-    debug!("Cleared the OLED display.");
+    let mut client = oled_client(default_timeout())?;
+
+    client.clear().call().map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Cleared the OLED display.");
 
     Ok(())
 }
@@ -41,35 +96,141 @@ pub fn draw(
     x_coord: i32,
     y_coord: i32,
 ) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok("success".to_string());
+    }
 
-    client.draw(bytes, width, height, x_coord, y_coord).call()?;
-    debug!("Drew to the OLED display.");
+    let mut client = oled_client(default_timeout())?;
+
+    client
+        .draw(bytes, width, height, x_coord, y_coord)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Drew to the OLED display.");
 
     Ok("success".to_string())
 }
 
+/// Number of light modules drawn around a QR code so that it can be scanned
+/// against the unlit background of the display.
+#[cfg(feature = "qr")]
+const QR_QUIET_ZONE: usize = 2;
+
+/// Encodes the given data as a QR code and draws it to the OLED display by
+/// calling the `peach-oled` `draw` method. Each module is scaled up to the
+/// largest whole number of pixels which fits within the 64 pixel display height.
+///
+/// Returns an error if the data is too long to fit at one pixel per module.
+///
+/// # Arguments
+///
+/// * `data` - A string slice containing the data to encode.
+/// * `x_coord` - A 32 byte signed int.
+/// * `y_coord` - A 32 byte signed int.
+#[cfg(feature = "qr")]
+pub fn draw_qr(data: &str, x_coord: i32, y_coord: i32) -> std::result::Result<String, PeachError> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::L)
+        .map_err(|_| PeachError::QrCodeTooLarge { len: data.len() })?;
+    // modules per side, including the quiet zone on each edge
+    let modules = code.width() + 2 * QR_QUIET_ZONE;
+    let scale = DISPLAY_HEIGHT as usize / modules;
+    if scale == 0 {
+        return Err(PeachError::QrCodeTooLarge { len: data.len() });
+    }
+    let size = modules * scale;
+    // the bitmap is packed one bit per pixel, with each row padded to a whole byte
+    let row_bytes = size.div_ceil(8);
+    let mut bytes = vec![0u8; row_bytes * size];
+    for y in 0..size {
+        for x in 0..size {
+            let module_x = (x / scale).checked_sub(QR_QUIET_ZONE);
+            let module_y = (y / scale).checked_sub(QR_QUIET_ZONE);
+            let is_dark = match (module_x, module_y) {
+                (Some(mx), Some(my)) if mx < code.width() && my < code.width() => {
+                    code[(mx, my)] == Color::Dark
+                }
+                _ => false,
+            };
+            // light modules are lit pixels so the code reads correctly on the display
+            if !is_dark {
+                bytes[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    draw(bytes, size as u32, size as u32, x_coord, y_coord)
+}
+
+/// Number of bars in the icon drawn by `draw_signal_bars`.
+const SIGNAL_BARS: u32 = 4;
+/// Width in pixels of each bar, and of the gap to the right of it.
+const SIGNAL_BAR_WIDTH: u32 = 3;
+const SIGNAL_BAR_GAP: u32 = 1;
+/// Height in pixels of the shortest bar, with each following bar this much taller.
+const SIGNAL_BAR_STEP: u32 = 3;
+
+/// Draws a WiFi signal strength icon of four bars of increasing height, with a
+/// bar filled for each quarter of `percent` (e.g. from `network_client::rssi_percent`).
+/// Unfilled bars are drawn as outlines. At 0% every bar is unfilled and the icon
+/// is crossed out. Percentages above 100 are treated as 100.
+///
+/// # Arguments
+///
+/// * `x_coord` - A 32 byte signed int.
+/// * `y_coord` - A 32 byte signed int.
+/// * `percent` - The signal quality, from 0 to 100.
+pub fn draw_signal_bars(
+    x_coord: i32,
+    y_coord: i32,
+    percent: u8,
+) -> std::result::Result<String, PeachError> {
+    let percent = u32::from(percent.min(100));
+    let filled_bars = (percent * SIGNAL_BARS).div_ceil(100);
+    let width = SIGNAL_BARS * (SIGNAL_BAR_WIDTH + SIGNAL_BAR_GAP);
+    let height = SIGNAL_BARS * SIGNAL_BAR_STEP;
+    // the bitmap is packed one bit per pixel, with each row padded to a whole byte
+    let row_bytes = width.div_ceil(8);
+    let mut bytes = vec![0u8; (row_bytes * height) as usize];
+    let mut set_pixel = |x: u32, y: u32| {
+        bytes[(y * row_bytes + x / 8) as usize] |= 0x80 >> (x % 8);
+    };
+    for bar in 0..SIGNAL_BARS {
+        let left = bar * (SIGNAL_BAR_WIDTH + SIGNAL_BAR_GAP);
+        let right = left + SIGNAL_BAR_WIDTH - 1;
+        // bars are aligned to the bottom of the icon
+        let top = height - (bar + 1) * SIGNAL_BAR_STEP;
+        for y in top..height {
+            for x in left..=right {
+                let is_edge = x == left || x == right || y == top || y == height - 1;
+                if bar < filled_bars || is_edge {
+                    set_pixel(x, y);
+                }
+            }
+        }
+    }
+    if filled_bars == 0 {
+        // cross out the icon with its two diagonals
+        for y in 0..height {
+            let x = y * (width - 1) / (height - 1);
+            set_pixel(x, y);
+            set_pixel(width - 1 - x, y);
+        }
+    }
+
+    draw(bytes, width, height, x_coord, y_coord)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-oled`
 /// `flush` method.
 pub fn flush() -> std::result::Result<(), PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
 
-    client.flush().call()?;
-    debug!("Flushed the OLED display.");
+    client.flush().call().map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Flushed the OLED display.");
 
     Ok(())
 }
@@ -77,21 +238,47 @@ pub fn flush() -> std::result::Result<(), PeachError> {
 /// Creates a JSON-RPC client with http transport and calls the `peach-oled`
 /// `ping` method.
 pub fn ping() -> std::result::Result<(), PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
 
-    client.ping().call()?;
-    debug!("Pinged the OLED microservice.");
+    client.ping().call().map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Pinged the OLED microservice.");
 
     Ok(())
 }
 
+/// Returns true if `peach-oled` responds to a `ping` within a short timeout.
+/// UI code can use this to skip rendering on devices without a display rather
+/// than handling a connection error from every call. Always returns true in
+/// no-op mode (see `PEACH_OLED_NOOP`), since every call then succeeds.
+pub fn is_available() -> bool {
+    if noop_mode() {
+        return true;
+    }
+
+    match oled_client(Some(AVAILABILITY_TIMEOUT)) {
+        Ok(mut client) => client.ping().call().is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-oled`
+/// `version` method, returning the version of the running microservice.
+pub fn version() -> std::result::Result<String, PeachError> {
+    if noop_mode() {
+        return Ok("noop".to_string());
+    }
+
+    let mut client = oled_client(default_timeout())?;
+
+    let response = client.version().call().map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-oled`
 /// `power` method.
 ///
@@ -99,17 +286,92 @@ pub fn ping() -> std::result::Result<(), PeachError> {
 ///
 /// * `power` - A boolean expression
 pub fn power(on: bool) -> std::result::Result<(), PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
+
+    client.power(on).call().map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Toggled the OLED display power.");
+
+    Ok(())
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-oled`
+/// `reset_idle` method, which restarts the idle timer and wakes the display if
+/// the screensaver is active. Should be called whenever there is user activity.
+pub fn reset_idle() -> std::result::Result<(), PeachError> {
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
 
-    client.power(on).call()?;
-    debug!("Toggled the OLED display power.");
+    client.reset_idle().call().map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Reset the OLED idle timer.");
+
+    Ok(())
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-oled`
+/// `screensaver` method. When enabled, `peach-oled` periodically shifts the
+/// display content by a pixel and blanks the display once it has been idle for
+/// longer than the idle timeout, protecting the panel from burn-in.
+///
+/// The idle timeout (in seconds) is read from the `PEACH_OLED_IDLE_TIMEOUT`
+/// environment variable and defaults to 300.
+///
+/// # Arguments
+///
+/// * `enable` - A boolean expression
+pub fn screensaver(enable: bool) -> std::result::Result<(), PeachError> {
+    let idle_timeout = env::var("PEACH_OLED_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(300);
+
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
+
+    client
+        .screensaver(enable, idle_timeout)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Toggled the OLED screensaver.");
+
+    Ok(())
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-oled`
+/// `set_orientation` method, which sets the SSD1306 segment and COM remap so
+/// that the display can be flipped to suit how the panel is mounted.
+///
+/// The SSD1306 can only mirror its output, so 0 and 180 degrees are the only
+/// supported rotations; any other angle returns an error without making a call.
+///
+/// # Arguments
+///
+/// * `rotation` - A 16 byte unsigned int (0 or 180).
+pub fn set_orientation(rotation: u16) -> std::result::Result<(), PeachError> {
+    if rotation != 0 && rotation != 180 {
+        return Err(PeachError::UnsupportedRotation { rotation });
+    }
+
+    if noop_mode() {
+        return Ok(());
+    }
+
+    let mut client = oled_client(default_timeout())?;
+
+    client
+        .set_orientation(rotation)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Set the OLED display orientation to {} degrees.", rotation);
 
     Ok(())
 }
@@ -129,21 +391,208 @@ pub fn write(
     string: &str,
     font_size: &str,
 ) -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for OLED client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_OLED_SERVER").unwrap_or_else(|_| "127.0.0.1:5112".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_oled service.");
-    let mut client = PeachOledClient::new(transport_handle);
+    if noop_mode() {
+        return Ok("success".to_string());
+    }
+
+    let mut client = oled_client(default_timeout())?;
+
+    client
+        .write(x_coord, y_coord, string, font_size)
+        .call()
+        .map_err(rpc_error(SERVICE))?;
+    debug!(target: LOG_TARGET, "Wrote to the OLED display.");
+
+    Ok("success".to_string())
+}
 
-    client.write(x_coord, y_coord, string, font_size).call()?;
-    debug!("Wrote to the OLED display.");
+/// Height of the OLED display in pixels.
+pub const DISPLAY_HEIGHT: i32 = 64;
+
+/// Returns the line height in pixels for a `peach-oled` font size string
+/// (`6x8`, `6x12`, `8x16` or `12x16`).
+fn font_height(font_size: &str) -> Option<i32> {
+    match font_size {
+        "6x8" => Some(8),
+        "6x12" => Some(12),
+        "8x16" | "12x16" => Some(16),
+        _ => None,
+    }
+}
+
+/// Creates a JSON-RPC client with http transport and renders a menu on the
+/// OLED display by calling the `peach-oled` `clear`, `write` and `flush`
+/// methods. The selected item is marked with a `>` prefix and the visible
+/// window scrolls to keep it on screen when there are more items than fit.
+///
+/// # Arguments
+///
+/// * `items` - A slice of string slices containing the menu item labels.
+/// * `selected` - The index of the selected item (clamped to the last item).
+/// * `font_size` - A reference to a string slice
+pub fn draw_menu(
+    items: &[&str],
+    selected: usize,
+    font_size: &str,
+) -> std::result::Result<String, PeachError> {
+    let line_height = font_height(font_size).ok_or_else(|| PeachError::InvalidFontSize {
+        font_size: font_size.to_string(),
+    })?;
+    let visible_lines = (DISPLAY_HEIGHT / line_height) as usize;
+    // clamp the selection rather than panicking on an out-of-range index
+    let selected = selected.min(items.len().saturating_sub(1));
+    // scroll the window so that the selected item is always the last visible line
+    // once the selection moves past the first screen of items
+    let first_visible = (selected + 1).saturating_sub(visible_lines);
+
+    let mut frame = OledFrame::new().clear();
+    for (line, (index, item)) in items
+        .iter()
+        .enumerate()
+        .skip(first_visible)
+        .take(visible_lines)
+        .enumerate()
+    {
+        let marker = if index == selected { ">" } else { " " };
+        let label = format!("{} {}", marker, item);
+        frame = frame.write(0, line as i32 * line_height, &label, font_size);
+    }
+    frame.send()?;
+    debug!(target: LOG_TARGET, "Drew a menu to the OLED display.");
 
     Ok("success".to_string())
 }
 
+/// A single drawing operation queued in an `OledFrame`.
+#[derive(Debug)]
+enum OledOp {
+    Clear,
+    Draw {
+        bytes: Vec<u8>,
+        width: u32,
+        height: u32,
+        x_coord: i32,
+        y_coord: i32,
+    },
+    Write {
+        x_coord: i32,
+        y_coord: i32,
+        string: String,
+        font_size: String,
+    },
+}
+
+/// Builder which accumulates `clear`, `draw` and `write` operations and sends
+/// them to `peach-oled` over a single connection, followed by a single `flush`.
+/// Rendering a full frame this way avoids a round-trip per operation and
+/// prevents partially-drawn frames from appearing on the display.
+///
+/// ```no_run
+/// use peach_lib::oled_client::OledFrame;
+///
+/// OledFrame::new()
+///     .clear()
+///     .write(0, 0, "PeachCloud", "6x8")
+///     .write(0, 16, "Online", "6x8")
+///     .send()
+///     .unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct OledFrame {
+    ops: Vec<OledOp>,
+    timeout: Option<Duration>,
+}
+
+impl OledFrame {
+    /// Creates an empty frame.
+    pub fn new() -> OledFrame {
+        OledFrame::default()
+    }
+
+    /// Sets the timeout for the requests made when the frame is sent, overriding
+    /// `PEACH_OLED_TIMEOUT_MS`. Each request which takes longer than this to
+    /// respond returns `PeachError::Timeout`.
+    pub fn timeout(mut self, duration: Duration) -> OledFrame {
+        self.timeout = Some(duration);
+        self
+    }
+
+    /// Queues a `clear` of the display buffer.
+    pub fn clear(mut self) -> OledFrame {
+        self.ops.push(OledOp::Clear);
+        self
+    }
+
+    /// Queues a `draw` of the given bitmap.
+    pub fn draw(
+        mut self,
+        bytes: Vec<u8>,
+        width: u32,
+        height: u32,
+        x_coord: i32,
+        y_coord: i32,
+    ) -> OledFrame {
+        self.ops.push(OledOp::Draw {
+            bytes,
+            width,
+            height,
+            x_coord,
+            y_coord,
+        });
+        self
+    }
+
+    /// Queues a `write` of the given string.
+    pub fn write(mut self, x_coord: i32, y_coord: i32, string: &str, font_size: &str) -> OledFrame {
+        self.ops.push(OledOp::Write {
+            x_coord,
+            y_coord,
+            string: string.to_string(),
+            font_size: font_size.to_string(),
+        });
+        self
+    }
+
+    /// Creates a JSON-RPC client with http transport, performs each queued
+    /// operation in order and then calls the `peach-oled` `flush` method once.
+    pub fn send(self) -> std::result::Result<(), PeachError> {
+        if noop_mode() {
+            return Ok(());
+        }
+
+        let mut client = oled_client(self.timeout.or_else(default_timeout))?;
+
+        for op in self.ops {
+            match op {
+                OledOp::Clear => client.clear().call().map_err(rpc_error(SERVICE))?,
+                OledOp::Draw {
+                    bytes,
+                    width,
+                    height,
+                    x_coord,
+                    y_coord,
+                } => client
+                    .draw(bytes, width, height, x_coord, y_coord)
+                    .call()
+                    .map_err(rpc_error(SERVICE))?,
+                OledOp::Write {
+                    x_coord,
+                    y_coord,
+                    string,
+                    font_size,
+                } => client
+                    .write(x_coord, y_coord, &string, &font_size)
+                    .call()
+                    .map_err(rpc_error(SERVICE))?,
+            };
+        }
+        client.flush().call().map_err(rpc_error(SERVICE))?;
+        debug!(target: LOG_TARGET, "Sent a frame to the OLED display.");
+
+        Ok(())
+    }
+}
+
 jsonrpc_client!(pub struct PeachOledClient {
     /// Creates a JSON-RPC request to clear the OLED display.
     pub fn clear(&mut self) -> RpcRequest<String>;
@@ -160,6 +609,18 @@ jsonrpc_client!(pub struct PeachOledClient {
 /// Creates a JSON-RPC request to toggle the power of the OLED display.
     pub fn power(&mut self, on: bool) -> RpcRequest<String>;
 
+    /// Creates a JSON-RPC request to reset the idle timer of the OLED display.
+    pub fn reset_idle(&mut self) -> RpcRequest<String>;
+
+    /// Creates a JSON-RPC request to toggle the OLED screensaver.
+    pub fn screensaver(&mut self, enable: bool, idle_timeout: u64) -> RpcRequest<String>;
+
+    /// Creates a JSON-RPC request to set the rotation of the OLED display.
+    pub fn set_orientation(&mut self, rotation: u16) -> RpcRequest<String>;
+
+    /// Creates a JSON-RPC request to get the version of the `peach-oled` microservice.
+    pub fn version(&mut self) -> RpcRequest<String>;
+
     /// Creates a JSON-RPC request to write to the OLED display.
     pub fn write(&mut self, x_coord: i32, y_coord: i32, string: &str, font_size: &str) -> RpcRequest<String>;
 });
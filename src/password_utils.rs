@@ -1,33 +1,264 @@
-use crate::config_manager::{get_peachcloud_domain, load_peach_config};
+use crate::config_manager::{self, get_peachcloud_domain, load_peach_config};
 use crate::error::PeachError;
 use crate::error::StdIoError;
 use crate::sbot_client;
+use fslock::LockFile;
+use log::{debug, warn};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
-use std::iter;
-use std::process::Command;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::password=debug`.
+const LOG_TARGET: &str = "peach_lib::password";
 
 /// filepath where nginx basic auth passwords are stored
+/// (overridable with the PEACH_HTPASSWD_FILE env var)
 pub const HTPASSWD_FILE: &str = "/var/lib/peachcloud/passwords/htpasswd";
 /// filepath where random temporary password is stored for password resets
+/// (overridable with the PEACH_HTPASSWD_TEMPORARY_PASSWORD_FILE env var)
 pub const HTPASSWD_TEMPORARY_PASSWORD_FILE: &str =
     "/var/lib/peachcloud/passwords/temporary_password";
+/// filepath where bcrypt hashes of recently used passwords are stored
+/// (kept in the same directory as the htpasswd file, see `password_history_file`)
+pub const PASSWORD_HISTORY_FILE: &str = "/var/lib/peachcloud/passwords/password_history";
+/// default number of previous passwords which may not be reused
+/// (overridable with the PEACH_PASSWORD_HISTORY_SIZE env var)
+pub const PASSWORD_HISTORY_SIZE: usize = 5;
+/// bcrypt cost used when hashing passwords for the password history
+const PASSWORD_HISTORY_BCRYPT_COST: u32 = 10;
+/// filepath where failed password verification attempts are recorded
+/// (kept in the same directory as the htpasswd file, see `login_attempts_file`)
+pub const LOGIN_ATTEMPTS_FILE: &str = "/var/lib/peachcloud/passwords/login_attempts.json";
+/// default number of failed verifications allowed within the attempt window
+/// (overridable with the PEACH_MAX_PASSWORD_ATTEMPTS env var)
+pub const MAX_PASSWORD_ATTEMPTS: usize = 5;
+/// default length of the attempt window in seconds
+/// (overridable with the PEACH_PASSWORD_ATTEMPT_WINDOW_SECS env var)
+pub const PASSWORD_ATTEMPT_WINDOW_SECS: i64 = 300;
+/// filepath where the current password reset token is stored
+/// (kept in the same directory as the htpasswd file, see `reset_token_file`)
+pub const RESET_TOKEN_FILE: &str = "/var/lib/peachcloud/passwords/reset_token.json";
+/// default number of seconds a reset token remains valid
+/// (overridable with the PEACH_RESET_TOKEN_TTL_SECS env var)
+pub const RESET_TOKEN_TTL_SECS: i64 = 3600;
+/// length of generated reset tokens
+const RESET_TOKEN_LENGTH: usize = 32;
+/// file mode for the htpasswd file (owner read/write, group read so nginx can check credentials)
+pub const HTPASSWD_FILE_MODE: u32 = 0o640;
+/// file mode for the temporary password file (owner read/write only)
+pub const HTPASSWD_TEMPORARY_PASSWORD_FILE_MODE: u32 = 0o600;
 /// the username of the user for nginx basic auth
 pub const PEACHCLOUD_AUTH_USER: &str = "admin";
+/// default length of generated temporary passwords
+/// (overridable with the PEACH_RESET_PASSWORD_LENGTH env var)
+pub const TEMPORARY_PASSWORD_LENGTH: usize = 10;
+/// symbols which may be included in generated passwords
+const PASSWORD_SYMBOLS: &[u8] = b"!#$%&()*+,-./:;<=>?@[]^_{|}~";
+/// subject line of password reset emails
+pub const PASSWORD_RESET_EMAIL_SUBJECT: &str = "PeachCloud password reset";
+
+/// The set of characters used by `generate_password`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharSet {
+    /// ASCII letters and digits.
+    Alphanumeric,
+    /// ASCII letters, digits and punctuation symbols.
+    AlphanumericSymbols,
+}
+
+/// A function which sends an email, given the recipient address, subject and body.
+/// Used as a pluggable fallback channel by `send_password_reset_with_sender`.
+pub type EmailSender = fn(to: &str, subject: &str, body: &str) -> Result<(), PeachError>;
+
+/// Returns the filepath of the htpasswd file
+pub fn htpasswd_file() -> String {
+    env::var("PEACH_HTPASSWD_FILE").unwrap_or_else(|_| HTPASSWD_FILE.to_string())
+}
+
+/// Returns the filepath of the temporary password file
+pub fn temporary_password_file() -> String {
+    env::var("PEACH_HTPASSWD_TEMPORARY_PASSWORD_FILE")
+        .unwrap_or_else(|_| HTPASSWD_TEMPORARY_PASSWORD_FILE.to_string())
+}
+
+/// Helper function which returns the path of the file with the same name as the given
+/// default path, in the directory of the htpasswd file, so that overriding the htpasswd
+/// file with PEACH_HTPASSWD_FILE also moves the files kept alongside it
+fn beside_htpasswd_file(default_path: &str) -> String {
+    let file_name = std::path::Path::new(default_path)
+        .file_name()
+        .unwrap_or_default();
+    std::path::Path::new(&htpasswd_file())
+        .with_file_name(file_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the filepath of the password history file
+pub fn password_history_file() -> String {
+    beside_htpasswd_file(PASSWORD_HISTORY_FILE)
+}
+
+/// Returns the filepath of the login attempts file
+pub fn login_attempts_file() -> String {
+    beside_htpasswd_file(LOGIN_ATTEMPTS_FILE)
+}
+
+/// Returns the filepath of the reset token file
+pub fn reset_token_file() -> String {
+    beside_htpasswd_file(RESET_TOKEN_FILE)
+}
+
+/// Timestamps (unix seconds) of recent failed password verifications, keyed by username
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoginAttempts {
+    failures: HashMap<String, Vec<i64>>,
+}
+
+/// Helper function which returns the failed attempt limit and window length
+fn attempt_limits() -> (usize, i64) {
+    let max_attempts = env::var("PEACH_MAX_PASSWORD_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or(MAX_PASSWORD_ATTEMPTS);
+    let window_secs = env::var("PEACH_PASSWORD_ATTEMPT_WINDOW_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(PASSWORD_ATTEMPT_WINDOW_SECS);
+    (max_attempts, window_secs)
+}
+
+/// Helper function which locks the login attempts file, so that concurrent verifications
+/// cannot overwrite each other's attempts. The lock is released when it is dropped.
+fn lock_login_attempts() -> Result<LockFile, PeachError> {
+    let lock_path = format!("{}.lock", login_attempts_file());
+    let mut lock = LockFile::open(lock_path.as_str()).context(StdIoError {
+        msg: format!("Failed to open {}", lock_path),
+    })?;
+    lock.lock().context(StdIoError {
+        msg: format!("Failed to lock {}", lock_path),
+    })?;
+    Ok(lock)
+}
+
+/// Helper function which loads the recorded failed attempts.
+/// A corrupt attempts file is reset, so that it neither locks the user out for good
+/// nor stops further failures from being recorded. Must be called with the lock held.
+fn load_login_attempts() -> Result<LoginAttempts, PeachError> {
+    let path = login_attempts_file();
+    if !std::path::Path::new(&path).exists() {
+        return Ok(LoginAttempts::default());
+    }
+    let contents = fs::read_to_string(&path).context(StdIoError {
+        msg: "Failed to read login attempts",
+    })?;
+    match serde_json::from_str(&contents) {
+        Ok(attempts) => Ok(attempts),
+        Err(err) => {
+            warn!(target: LOG_TARGET, "Resetting corrupt login attempts file: {}", err);
+            let attempts = LoginAttempts::default();
+            save_login_attempts(&attempts)?;
+            Ok(attempts)
+        }
+    }
+}
+
+/// Helper function which saves the recorded failed attempts
+fn save_login_attempts(attempts: &LoginAttempts) -> Result<(), PeachError> {
+    let json_str = serde_json::to_string(attempts)?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(HTPASSWD_TEMPORARY_PASSWORD_FILE_MODE)
+        .open(login_attempts_file())
+        .context(StdIoError {
+            msg: "Failed to open login attempts",
+        })?;
+    file.write_all(json_str.as_bytes()).context(StdIoError {
+        msg: "Failed to write login attempts",
+    })?;
+    Ok(())
+}
+
+/// Returns PeachError::TooManyAttempts, with the number of seconds until the next
+/// attempt is allowed, if the user has reached the failed attempt limit within the window.
+fn check_login_attempts(username: &str) -> Result<(), PeachError> {
+    let (max_attempts, window_secs) = attempt_limits();
+    let now = chrono::offset::Utc::now().timestamp();
+    // an unreadable attempts file must not lock the user out, so it is treated as empty
+    let attempts = lock_login_attempts()
+        .and_then(|_lock| load_login_attempts())
+        .unwrap_or_else(|err| {
+            warn!(target: LOG_TARGET, "Failed to load password verification attempts: {}", err);
+            LoginAttempts::default()
+        });
+    let recent_failures: Vec<i64> = attempts
+        .failures
+        .get(username)
+        .map(|failures| {
+            failures
+                .iter()
+                .copied()
+                .filter(|timestamp| now - timestamp < window_secs)
+                .collect()
+        })
+        .unwrap_or_default();
+    if recent_failures.len() >= max_attempts {
+        // the next attempt is allowed once the oldest recent failure leaves the window
+        let oldest = recent_failures.iter().min().copied().unwrap_or(now);
+        let retry_after_secs = (oldest + window_secs - now).max(0) as u64;
+        return Err(PeachError::TooManyAttempts { retry_after_secs });
+    }
+    Ok(())
+}
+
+/// Records a failed verification for the user, or clears their failures on success.
+/// This is best-effort: failing to persist the attempts is logged but does not fail
+/// the verification.
+fn record_login_attempt(username: &str, success: bool) {
+    let (_, window_secs) = attempt_limits();
+    let now = chrono::offset::Utc::now().timestamp();
+    let result = lock_login_attempts().and_then(|_lock| {
+        let mut attempts = load_login_attempts()?;
+        if success {
+            attempts.failures.remove(username);
+        } else {
+            let failures = attempts.failures.entry(username.to_string()).or_default();
+            failures.retain(|timestamp| now - timestamp < window_secs);
+            failures.push(now);
+        }
+        save_login_attempts(&attempts)
+    });
+    if let Err(err) = result {
+        warn!(target: LOG_TARGET, "Failed to record password verification attempt: {}", err);
+    }
+}
 
 /// Returns Ok(()) if the supplied password is correct,
 /// and returns Err if the supplied password is incorrect.
+/// After too many failed attempts within the attempt window,
+/// returns PeachError::TooManyAttempts without checking the password.
 pub fn verify_password(password: &str) -> Result<(), PeachError> {
+    check_login_attempts(PEACHCLOUD_AUTH_USER)?;
     let output = Command::new("/usr/bin/htpasswd")
         .arg("-vb")
-        .arg(HTPASSWD_FILE)
+        .arg(htpasswd_file())
         .arg(PEACHCLOUD_AUTH_USER)
         .arg(password)
         .output()
         .context(StdIoError {
             msg: "htpasswd is not installed",
         })?;
+    record_login_attempt(PEACHCLOUD_AUTH_USER, output.status.success());
     if output.status.success() {
         Ok(())
     } else {
@@ -47,11 +278,43 @@ pub fn validate_new_passwords(new_password1: &str, new_password2: &str) -> Resul
     }
 }
 
-/// Uses htpasswd to set a new password for the admin user
+/// Helper function which restricts the permissions of a file which may have been
+/// created before its mode was set on open.
+fn restrict_permissions(path: &str, mode: u32) -> Result<(), PeachError> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).context(StdIoError {
+        msg: format!("Failed to set permissions of {}", path),
+    })?;
+    Ok(())
+}
+
+/// Helper function which makes sure a file written by htpasswd exists with the given mode
+/// before htpasswd writes a hash to it. htpasswd creates files using the default umask,
+/// but keeps the permissions of a file which already exists.
+fn prepare_htpasswd_file(path: &str, mode: u32) -> Result<(), PeachError> {
+    let existed = std::path::Path::new(path).exists();
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(mode)
+        .open(path)
+        .context(StdIoError {
+            msg: format!("Failed to create {}", path),
+        })?;
+    // the mode given to open only applies when the file is created
+    if existed {
+        restrict_permissions(path, mode)?;
+    }
+    Ok(())
+}
+
+/// Uses htpasswd to set a new password for the admin user.
+/// The password is also recorded in the password history on a best-effort basis.
 pub fn set_new_password(new_password: &str) -> Result<(), PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    prepare_htpasswd_file(&htpasswd_file(), HTPASSWD_FILE_MODE)?;
     let output = Command::new("/usr/bin/htpasswd")
         .arg("-cb")
-        .arg(HTPASSWD_FILE)
+        .arg(htpasswd_file())
         .arg(PEACHCLOUD_AUTH_USER)
         .arg(new_password)
         .output()
@@ -59,6 +322,11 @@ pub fn set_new_password(new_password: &str) -> Result<(), PeachError> {
             msg: "htpasswd is not installed",
         })?;
     if output.status.success() {
+        // the password has already been changed, so failing to record it in the
+        // history must not be reported as a failure to change the password
+        if let Err(err) = record_password_history(new_password) {
+            warn!(target: LOG_TARGET, "Failed to record password history: {}", err);
+        }
         Ok(())
     } else {
         let err_output = String::from_utf8(output.stderr)?;
@@ -66,12 +334,93 @@ pub fn set_new_password(new_password: &str) -> Result<(), PeachError> {
     }
 }
 
+/// Helper function which returns the number of previous passwords to remember
+fn password_history_size() -> usize {
+    env::var("PEACH_PASSWORD_HISTORY_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(PASSWORD_HISTORY_SIZE)
+}
+
+/// Helper function which reads the bcrypt hashes in the password history, most recent last
+fn read_password_history() -> Result<Vec<String>, PeachError> {
+    let path = password_history_file();
+    if !std::path::Path::new(&path).exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path).context(StdIoError {
+        msg: "Failed to read password history",
+    })?;
+    Ok(contents.lines().map(|line| line.to_string()).collect())
+}
+
+/// Helper function which adds the bcrypt hash of a password to the password history,
+/// keeping only the most recent hashes
+fn record_password_history(password: &str) -> Result<(), PeachError> {
+    let hash = bcrypt::hash(password, PASSWORD_HISTORY_BCRYPT_COST).map_err(|err| {
+        PeachError::FailedToSetNewPassword {
+            msg: err.to_string(),
+        }
+    })?;
+    let mut history = read_password_history()?;
+    history.push(hash);
+    let excess = history.len().saturating_sub(password_history_size());
+    history.drain(..excess);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(HTPASSWD_FILE_MODE)
+        .open(password_history_file())
+        .context(StdIoError {
+            msg: "Failed to open password history",
+        })?;
+    for hash in history {
+        writeln!(file, "{}", hash).context(StdIoError {
+            msg: "Failed to write password history",
+        })?;
+    }
+    // the mode given to open only applies when the file is created
+    restrict_permissions(&password_history_file(), HTPASSWD_FILE_MODE)
+}
+
+/// Returns Ok(()) if the given password does not match any recently used password,
+/// and returns PeachError::PasswordReused if it does.
+pub fn check_password_not_reused(password: &str) -> Result<(), PeachError> {
+    for hash in read_password_history()? {
+        // hashes which cannot be parsed are ignored rather than blocking the change
+        if bcrypt::verify(password, &hash).unwrap_or(false) {
+            return Err(PeachError::PasswordReused);
+        }
+    }
+    Ok(())
+}
+
+/// Changes the admin password after verifying the current password,
+/// checking that the new passwords match and that the new password
+/// has not been used recently.
+pub fn change_password(
+    old_password: &str,
+    new_password1: &str,
+    new_password2: &str,
+) -> Result<(), PeachError> {
+    verify_password(old_password)?;
+    validate_new_passwords(new_password1, new_password2)?;
+    check_password_not_reused(new_password1)?;
+    set_new_password(new_password1)
+}
+
 /// Uses htpasswd to set a new temporary password for the admin user
 /// which can be used to reset the permanent password
 pub fn set_new_temporary_password(new_password: &str) -> Result<(), PeachError> {
+    config_manager::ensure_not_in_maintenance_mode()?;
+    prepare_htpasswd_file(
+        &temporary_password_file(),
+        HTPASSWD_TEMPORARY_PASSWORD_FILE_MODE,
+    )?;
     let output = Command::new("/usr/bin/htpasswd")
         .arg("-cb")
-        .arg(HTPASSWD_TEMPORARY_PASSWORD_FILE)
+        .arg(temporary_password_file())
         .arg(PEACHCLOUD_AUTH_USER)
         .arg(new_password)
         .output()
@@ -92,7 +441,7 @@ pub fn verify_temporary_password(password: &str) -> Result<(), PeachError> {
     // TODO: confirm temporary password has not expired
     let output = Command::new("/usr/bin/htpasswd")
         .arg("-vb")
-        .arg(HTPASSWD_TEMPORARY_PASSWORD_FILE)
+        .arg(temporary_password_file())
         .arg(PEACHCLOUD_AUTH_USER)
         .arg(password)
         .output()
@@ -106,18 +455,220 @@ pub fn verify_temporary_password(password: &str) -> Result<(), PeachError> {
     }
 }
 
-/// generates a temporary password and sends it via ssb dm
-/// to the ssb id configured to be the admin of the peachcloud device
-pub fn send_password_reset() -> Result<(), PeachError> {
-    // first generate a new random password of ascii characters
+/// Generates a random password of the given length from the given set of characters,
+/// using the thread-local random number generator.
+/// Also suitable for generating other secrets.
+pub fn generate_password(len: usize, charset: CharSet) -> String {
     let mut rng = thread_rng();
-    let temporary_password: String = iter::repeat(())
-        .map(|()| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(10)
-        .collect();
+    match charset {
+        CharSet::Alphanumeric => (&mut rng)
+            .sample_iter(Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect(),
+        CharSet::AlphanumericSymbols => {
+            let alphanumeric: Vec<u8> = (b'0'..=b'9')
+                .chain(b'A'..=b'Z')
+                .chain(b'a'..=b'z')
+                .collect();
+            let chars = [alphanumeric.as_slice(), PASSWORD_SYMBOLS].concat();
+            (0..len)
+                .map(|_| char::from(chars[rng.gen_range(0..chars.len())]))
+                .collect()
+        }
+    }
+}
+
+/// A single-use password reset token and the time (unix seconds) at which it expires
+#[derive(Debug, Serialize, Deserialize)]
+struct ResetToken {
+    token: String,
+    expires_at: i64,
+}
+
+/// Creates a new opaque password reset token, valid for PEACH_RESET_TOKEN_TTL_SECS
+/// (default one hour), and returns it so it can be included in a reset link.
+/// Only one token is valid at a time: creating a token invalidates any previous one.
+pub fn create_reset_token() -> Result<String, PeachError> {
+    let ttl_secs = env::var("PEACH_RESET_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(RESET_TOKEN_TTL_SECS);
+    let reset_token = ResetToken {
+        token: generate_password(RESET_TOKEN_LENGTH, CharSet::Alphanumeric),
+        expires_at: chrono::offset::Utc::now().timestamp() + ttl_secs,
+    };
+    let json_str = serde_json::to_string(&reset_token)?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(HTPASSWD_TEMPORARY_PASSWORD_FILE_MODE)
+        .open(reset_token_file())
+        .context(StdIoError {
+            msg: "Failed to open reset token file",
+        })?;
+    file.write_all(json_str.as_bytes()).context(StdIoError {
+        msg: "Failed to write reset token file",
+    })?;
+    Ok(reset_token.token)
+}
+
+/// Helper function which compares two byte strings in constant time (for strings of
+/// the same length), so that the time taken does not reveal how much of a secret matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Helper function which checks the supplied token against a stored reset token
+fn check_reset_token(path: &str, token: &str) -> Result<(), PeachError> {
+    let contents = fs::read_to_string(path).context(StdIoError {
+        msg: "Failed to read reset token file",
+    })?;
+    let reset_token: ResetToken = serde_json::from_str(&contents)?;
+    let now = chrono::offset::Utc::now().timestamp();
+    if constant_time_eq(reset_token.token.as_bytes(), token.as_bytes())
+        && now < reset_token.expires_at
+    {
+        Ok(())
+    } else {
+        Err(PeachError::InvalidResetToken)
+    }
+}
+
+/// Returns Ok(()) if the supplied reset token is the current token and has not expired,
+/// and returns PeachError::InvalidResetToken otherwise.
+pub fn verify_reset_token(token: &str) -> Result<(), PeachError> {
+    let path = reset_token_file();
+    if !std::path::Path::new(&path).exists() {
+        return Err(PeachError::InvalidResetToken);
+    }
+    check_reset_token(&path, token)
+}
+
+/// Counter which makes the names of claimed reset token files unique within the process
+static RESET_TOKEN_CLAIMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Verifies the supplied reset token and then invalidates it, so that it cannot be used again.
+/// The token file is first renamed to a name unique to this request, so that when two requests
+/// race to use the same token, only one of them can succeed.
+pub fn consume_reset_token(token: &str) -> Result<(), PeachError> {
+    let path = reset_token_file();
+    let claimed = format!(
+        "{}.{}-{}",
+        path,
+        std::process::id(),
+        RESET_TOKEN_CLAIMS.fetch_add(1, Ordering::SeqCst)
+    );
+    match fs::rename(&path, &claimed) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(PeachError::InvalidResetToken)
+        }
+        Err(err) => {
+            return Err(err).context(StdIoError {
+                msg: "Failed to claim reset token file",
+            })
+        }
+    }
+    let result = check_reset_token(&claimed, token);
+    if result.is_err() {
+        // put back the token this request failed to use, unless a new token has been
+        // created in the meantime (hard_link does not replace an existing file)
+        if let Err(err) = fs::hard_link(&claimed, &path) {
+            debug!(target: LOG_TARGET, "Did not restore unused reset token: {}", err);
+        }
+    }
+    if let Err(err) = fs::remove_file(&claimed) {
+        warn!(target: LOG_TARGET, "Failed to remove claimed reset token file: {}", err);
+    }
+    result
+}
+
+/// Invalidates the current reset token, if there is one, without changing any password.
+pub fn invalidate_reset_token() -> Result<(), PeachError> {
+    let path = reset_token_file();
+    if std::path::Path::new(&path).exists() {
+        fs::remove_file(&path).context(StdIoError {
+            msg: "Failed to remove reset token file",
+        })?;
+    }
+    Ok(())
+}
+
+/// Helper function which rejects a header value containing a line break, since sendmail -t
+/// reads the headers from the message and would treat the rest as extra headers
+fn check_header_value(name: &str, value: &str) -> Result<(), PeachError> {
+    if value.contains('\r') || value.contains('\n') {
+        return Err(PeachError::SendEmailError {
+            msg: format!("{} header contains a line break", name),
+        });
+    }
+    Ok(())
+}
+
+/// Sends an email by piping it to `/usr/sbin/sendmail -t`.
+/// This expects a sendmail-compatible MTA (e.g. msmtp or postfix) to be installed
+/// and configured with an SMTP relay which can deliver to the recipient.
+pub fn sendmail_sender(to: &str, subject: &str, body: &str) -> Result<(), PeachError> {
+    check_header_value("To", to)?;
+    check_header_value("Subject", subject)?;
+    let mut sendmail = Command::new("/usr/sbin/sendmail")
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(StdIoError {
+            msg: "sendmail is not installed",
+        })?;
+    if let Some(stdin) = sendmail.stdin.as_mut() {
+        write!(stdin, "To: {}\nSubject: {}\n\n{}\n", to, subject, body).context(StdIoError {
+            msg: "Failed to write email to sendmail",
+        })?;
+    }
+    let output = sendmail.wait_with_output().context(StdIoError {
+        msg: "Failed to run sendmail",
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let err_output = String::from_utf8(output.stderr)?;
+        Err(PeachError::SendEmailError { msg: err_output })
+    }
+}
+
+/// generates a temporary password and a reset token and sends them via ssb dm
+/// to the ssb id configured to be the admin of the peachcloud device,
+/// falling back to emailing the configured admin email using sendmail
+pub fn send_password_reset() -> Result<(), PeachError> {
+    send_password_reset_with_sender(sendmail_sender)
+}
+
+/// generates a temporary password and sends it via ssb dm
+/// to the ssb id configured to be the admin of the peachcloud device,
+/// with reset links containing a new single-use reset token (see `create_reset_token`).
+/// if the dm cannot be sent (e.g. on first boot, before sbot is configured)
+/// and an admin email is configured, the password is emailed using the given sender.
+pub fn send_password_reset_with_sender(email_sender: EmailSender) -> Result<(), PeachError> {
+    // first generate a new random password, with the length and character set
+    // read from the PEACH_RESET_PASSWORD_LENGTH and PEACH_RESET_PASSWORD_SYMBOLS env vars
+    let length = env::var("PEACH_RESET_PASSWORD_LENGTH")
+        .ok()
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(TEMPORARY_PASSWORD_LENGTH);
+    let charset = match env::var("PEACH_RESET_PASSWORD_SYMBOLS") {
+        Ok(value) if value == "true" => CharSet::AlphanumericSymbols,
+        _ => CharSet::Alphanumeric,
+    };
+    let temporary_password = generate_password(length, charset);
     // save this string as a new temporary password
     set_new_temporary_password(&temporary_password)?;
+    // and a reset token for the links, which replaces any previous token
+    let reset_token = create_reset_token()?;
     let domain = get_peachcloud_domain()?;
 
     // then send temporary password as a private ssb message to admin
@@ -125,8 +676,8 @@ pub fn send_password_reset() -> Result<(), PeachError> {
         "Your new temporary password is: {}
 
 If you are on the same WiFi network as your PeachCloud device you can reset your password \
-using this link: http://peach.local/reset_password",
-        temporary_password
+using this link: http://peach.local/reset_password?token={}",
+        temporary_password, reset_token
     );
     // if there is an external domain, then include remote link in message
     // otherwise dont include it
@@ -134,8 +685,8 @@ using this link: http://peach.local/reset_password",
         Some(domain) => {
             format!(
                 "\n\nOr if you are on a different WiFi network, you can reset your password \
-            using the the following link: {}/reset_password",
-                domain
+            using the the following link: {}/reset_password?token={}",
+                domain, reset_token
             )
         }
         None => "".to_string(),
@@ -143,8 +694,48 @@ using this link: http://peach.local/reset_password",
     msg += &remote_link;
     // finally send the message to the admins
     let peach_config = load_peach_config()?;
-    for ssb_admin_id in peach_config.ssb_admin_ids {
-        sbot_client::private_message(&msg, &ssb_admin_id)?;
+    match send_ssb_password_reset(&msg, &peach_config.ssb_admin_ids) {
+        Ok(()) => Ok(()),
+        Err(err) if peach_config.admin_email.is_empty() => Err(err),
+        // fall back to email if the ssb message could not be delivered
+        Err(err) => {
+            warn!(target: LOG_TARGET,
+                "Failed to send password reset via ssb, sending email: {}",
+                err
+            );
+            email_sender(
+                &peach_config.admin_email,
+                PASSWORD_RESET_EMAIL_SUBJECT,
+                &msg,
+            )
+        }
+    }
+}
+
+/// Helper function which sends the password reset message as a private ssb message
+/// to each admin, after checking that there is someone to send it to, that
+/// the local sbot is online, and that its identity is following (or is) each admin,
+/// since a message to an admin who is not followed may never be replicated to them
+fn send_ssb_password_reset(msg: &str, ssb_admin_ids: &[String]) -> Result<(), PeachError> {
+    if ssb_admin_ids.is_empty() {
+        return Err(PeachError::NoSsbAdminIds);
+    }
+    if !sbot_client::is_sbot_online()? {
+        return Err(PeachError::SbotOffline);
+    }
+    let sbot_id = sbot_client::whoami()?;
+    let following = sbot_client::following(&sbot_id)?;
+    if let Some(ssb_admin_id) = ssb_admin_ids
+        .iter()
+        .find(|ssb_admin_id| **ssb_admin_id != sbot_id && !following.contains(ssb_admin_id))
+    {
+        return Err(PeachError::AdminNotFollowed {
+            id: ssb_admin_id.to_string(),
+        });
+    }
+    debug!(target: LOG_TARGET, "Sending password reset from ssb id: {}", sbot_id);
+    for ssb_admin_id in ssb_admin_ids {
+        sbot_client::private_message(msg, ssb_admin_id)?;
     }
     Ok(())
 }
@@ -0,0 +1,114 @@
+//! Retry fallible calls to the PeachCloud microservices.
+//!
+//! Microservices may briefly be unavailable, for example while they restart
+//! after a config change. Rather than each client function implementing its
+//! own retry loop, any call returning a `PeachError` can be wrapped in `retry`.
+//!
+//! Some client functions, such as `network_client::ping`, retry by themselves
+//! when `PEACH_RPC_RETRIES` is set (see `with_retries`).
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+use rand::{thread_rng, Rng};
+
+use crate::error::PeachError;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::retry=debug`.
+const LOG_TARGET: &str = "peach_lib::retry";
+
+/// The delay before the first retry made by `with_retries`.
+pub const RPC_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Returns the number of times client functions which retry by themselves
+/// repeat a failed call, read from the `PEACH_RPC_RETRIES` environment
+/// variable. Calls are not retried when the variable is not set.
+pub fn rpc_retries() -> u32 {
+    env::var("PEACH_RPC_RETRIES")
+        .ok()
+        .and_then(|retries| retries.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Calls `f` up to `attempts` times, until it succeeds or returns an error
+/// which is not retryable (see `PeachError::is_retryable`).
+///
+/// After each failed attempt the calling thread sleeps, starting at `backoff`
+/// and doubling for every attempt. A random jitter of up to half the delay is
+/// added, so that clients which failed at the same time do not all retry
+/// together. The error from the final attempt is returned if every attempt fails.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use peach_lib::error::PeachError;
+/// use peach_lib::retry::retry;
+///
+/// let mut calls = 0;
+/// let result = retry(3, Duration::from_millis(1), || {
+///     calls += 1;
+///     if calls < 3 {
+///         Err(PeachError::Timeout)
+///     } else {
+///         Ok(calls)
+///     }
+/// });
+/// assert_eq!(result.unwrap(), 3);
+/// ```
+pub fn retry<T, F>(attempts: u32, backoff: Duration, f: F) -> Result<T, PeachError>
+where
+    F: FnMut() -> Result<T, PeachError>,
+{
+    retry_if(attempts, backoff, f, PeachError::is_retryable)
+}
+
+/// Same as `retry`, but only retries errors for which `should_retry` returns true.
+fn retry_if<T, F, P>(
+    attempts: u32,
+    backoff: Duration,
+    mut f: F,
+    should_retry: P,
+) -> Result<T, PeachError>
+where
+    F: FnMut() -> Result<T, PeachError>,
+    P: Fn(&PeachError) -> bool,
+{
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < attempts && should_retry(&err) => {
+                let jitter = thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+                let sleep = delay + Duration::from_millis(jitter);
+                debug!(
+                    target: LOG_TARGET,
+                    "Attempt {} of {} failed, retrying in {:?}: {}", attempt, attempts, sleep, err
+                );
+                thread::sleep(sleep);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Calls `f` up to `attempts` times like `retry`, waiting `RPC_RETRY_BACKOFF`
+/// (100ms, then 200ms, 400ms and so on) between attempts. Only
+/// `PeachError::ServiceUnavailable` is retried, since a refused connection means
+/// the request never reached the microservice. After a timeout or other http
+/// error the request may already have been carried out (e.g. a `connect`), so
+/// those errors are returned straight away, as are errors such as invalid params.
+pub fn with_retries<T, F>(attempts: u32, f: F) -> Result<T, PeachError>
+where
+    F: FnMut() -> Result<T, PeachError>,
+{
+    retry_if(attempts, RPC_RETRY_BACKOFF, f, |err| {
+        matches!(err, PeachError::ServiceUnavailable { .. })
+    })
+}
@@ -1,8 +1,19 @@
 //! Interfaces for monitoring and configuring go-sbot using sbotcli.
 //!
+use crate::config_manager::load_peach_config;
 use crate::error::PeachError;
+use log::warn;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::sbot=debug`.
+const LOG_TARGET: &str = "peach_lib::sbot";
 
 pub fn is_sbot_online() -> Result<bool, PeachError> {
     let output = Command::new("/usr/bin/systemctl")
@@ -15,24 +26,237 @@ pub fn is_sbot_online() -> Result<bool, PeachError> {
     Ok(is_running)
 }
 
-/// currently go-sbotcli determines where the working directory is
-/// using the home directory of th user that invokes it
-/// this could be changed to be supplied as CLI arg
-/// but for now all sbotcli commands must first become peach-go-sbot before running
-/// the sudoers file is configured to allow this to happen without a password
+/// the locations sbotcli should use to reach an sbot instance
+#[derive(Debug, Clone, Default)]
+pub struct SbotcliConfig {
+    /// the sbot repo directory, containing the `secret` key and the unix `socket`
+    pub repo: Option<PathBuf>,
+    /// the secret key file, overriding the one in `repo`
+    pub key: Option<PathBuf>,
+}
+
+impl SbotcliConfig {
+    /// reads the config from the `PEACH_SBOT_REPO` and `PEACH_SBOT_KEY` env vars
+    pub fn from_env() -> SbotcliConfig {
+        SbotcliConfig {
+            repo: env::var_os("PEACH_SBOT_REPO").map(PathBuf::from),
+            key: env::var_os("PEACH_SBOT_KEY").map(PathBuf::from),
+        }
+    }
+}
+
+/// returns an sbotcli command configured from the environment (see `SbotcliConfig::from_env`)
 pub fn sbotcli_command() -> Command {
-    let mut command = Command::new("sudo");
-    command
-        .arg("-u")
-        .arg("peach-go-sbot")
-        .arg("/usr/bin/sbotcli");
+    sbotcli_command_with(&SbotcliConfig::from_env())
+}
+
+/// by default go-sbotcli determines where the working directory is
+/// using the home directory of the user that invokes it,
+/// so sbotcli commands must first become peach-go-sbot before running
+/// (the sudoers file is configured to allow this to happen without a password).
+/// if a repo or key is configured, sbotcli is instead run as the current user
+/// and pointed at them with the `--key` and `--unixsock` flags
+pub fn sbotcli_command_with(config: &SbotcliConfig) -> Command {
+    if config.repo.is_none() && config.key.is_none() {
+        let mut command = Command::new("sudo");
+        command
+            .arg("-u")
+            .arg("peach-go-sbot")
+            .arg("/usr/bin/sbotcli");
+        return command;
+    }
+    let mut command = Command::new("/usr/bin/sbotcli");
+    let key = config
+        .key
+        .clone()
+        .or_else(|| config.repo.as_ref().map(|repo| repo.join("secret")));
+    if let Some(key) = key {
+        command.arg("--key").arg(key);
+    }
+    if let Some(repo) = &config.repo {
+        command.arg("--unixsock").arg(repo.join("socket"));
+    }
     command
 }
 
+/// maximum number of lines of sbotcli output captured by `output_bounded`
+pub const MAX_OUTPUT_LINES: usize = 10_000;
+/// maximum number of bytes of sbotcli output captured by `output_bounded`
+pub const MAX_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+
+/// the output of an sbotcli command, captured up to a maximum size
+#[derive(Debug)]
+pub struct BoundedOutput {
+    pub status: ExitStatus,
+    /// the captured lines of stdout
+    pub lines: Vec<String>,
+    pub stderr: String,
+    /// true if stdout exceeded the maximum size and the remainder was discarded
+    pub truncated: bool,
+}
+
+/// runs the command, reading stdout line by line and stopping once `max_lines` lines
+/// or `max_bytes` bytes have been read, so that querying a large feed cannot exhaust memory.
+/// if the output is truncated the command is killed.
+/// stderr is read on a separate thread, so that a command writing a lot to stderr
+/// cannot block, and at most `max_bytes` of it are kept.
+/// invalid utf-8 in the output is replaced rather than failing the whole read.
+pub fn output_bounded(
+    command: &mut Command,
+    max_lines: usize,
+    max_bytes: usize,
+) -> Result<BoundedOutput, PeachError> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr_reader = child.stderr.take().map(|mut child_stderr| {
+        thread::spawn(move || {
+            let mut captured = Vec::new();
+            let _ = (&mut child_stderr)
+                .take(max_bytes as u64)
+                .read_to_end(&mut captured);
+            // the rest is discarded, but still read so that the command does not block
+            let _ = std::io::copy(&mut child_stderr, &mut std::io::sink());
+            String::from_utf8_lossy(&captured).to_string()
+        })
+    });
+    let mut lines = Vec::new();
+    let mut bytes_read = 0;
+    let mut truncated = false;
+    if let Some(stdout) = child.stdout.take() {
+        let mut reader = BufReader::new(stdout);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            // a single line longer than the remaining space is not read in full either
+            let remaining = (max_bytes - bytes_read) as u64 + 1;
+            if (&mut reader).take(remaining).read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            if buf.ends_with(b"\n") {
+                buf.pop();
+                if buf.ends_with(b"\r") {
+                    buf.pop();
+                }
+            }
+            let line = String::from_utf8_lossy(&buf).to_string();
+            if lines.len() >= max_lines || bytes_read + line.len() > max_bytes {
+                truncated = true;
+                break;
+            }
+            bytes_read += line.len();
+            lines.push(line);
+        }
+    }
+    if truncated {
+        warn!(target: LOG_TARGET, "Truncated sbotcli output after {} lines", lines.len());
+        // the command may already have exited, in which case there is nothing to kill
+        let _ = child.kill();
+    }
+    let stderr = match stderr_reader {
+        Some(reader) => reader.join().unwrap_or_default(),
+        None => String::new(),
+    };
+    let status = child.wait()?;
+    Ok(BoundedOutput {
+        status,
+        lines,
+        stderr,
+        truncated,
+    })
+}
+
+/// a page of messages read from a feed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedPage {
+    pub messages: Vec<serde_json::Value>,
+    /// true if the feed had more messages than could be read
+    pub truncated: bool,
+}
+
+/// returns up to `limit` messages from the given feed, oldest first
+pub fn read_feed(feed_id: &str, limit: usize) -> Result<FeedPage, PeachError> {
+    validate_feed_id(feed_id)?;
+    let mut command = sbotcli_command();
+    command.arg("hist").arg(feed_id);
+    let output = output_bounded(&mut command, limit, MAX_OUTPUT_BYTES)?;
+    // a killed command does not exit successfully, so only check the status of complete reads
+    if !output.truncated && !output.status.success() {
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error reading feed: {}", output.stderr),
+        });
+    }
+    let messages = output
+        .lines
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    Ok(FeedPage {
+        messages,
+        truncated: output.truncated,
+    })
+}
+
+/// file recording the time of the last publish, used to rate limit publishing
+pub const LAST_PUBLISH_FILE: &str = "/var/lib/peachcloud/sbot_last_publish";
+
+/// returns the minimum number of seconds between publishes, read from the
+/// `PEACH_SBOT_MIN_PUBLISH_INTERVAL_SECS` env var.
+/// publishing is only rate limited when the variable is set, so interactive use is not throttled
+fn min_publish_interval() -> Option<i64> {
+    env::var("PEACH_SBOT_MIN_PUBLISH_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+}
+
+/// returns the file recording the time of the last publish, which can be overridden
+/// with the `PEACH_SBOT_LAST_PUBLISH_FILE` env var
+fn last_publish_file() -> String {
+    env::var("PEACH_SBOT_LAST_PUBLISH_FILE").unwrap_or_else(|_| LAST_PUBLISH_FILE.to_string())
+}
+
+/// returns PeachError::PublishRateLimited if rate limiting is enabled and the last publish
+/// was less than the minimum interval ago
+fn check_publish_rate() -> Result<(), PeachError> {
+    let min_interval = match min_publish_interval() {
+        Some(min_interval) => min_interval,
+        None => return Ok(()),
+    };
+    let now = chrono::offset::Utc::now().timestamp();
+    let last_publish = std::fs::read_to_string(last_publish_file())
+        .ok()
+        .and_then(|contents| contents.trim().parse::<i64>().ok());
+    if let Some(last_publish) = last_publish {
+        let retry_after_secs = last_publish + min_interval - now;
+        if retry_after_secs > 0 {
+            return Err(PeachError::PublishRateLimited {
+                retry_after_secs: retry_after_secs as u64,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// records now as the time of the last publish, if rate limiting is enabled.
+/// called after a successful publish, so that failed attempts do not count towards the limit
+fn record_publish() {
+    if min_publish_interval().is_none() {
+        return;
+    }
+    let now = chrono::offset::Utc::now().timestamp();
+    // failing to record the publish should not fail it, as it has already happened
+    if let Err(err) = std::fs::write(last_publish_file(), now.to_string()) {
+        warn!(target: LOG_TARGET, "Failed to record time of last publish: {}", err);
+    }
+}
+
 pub fn post(msg: &str) -> Result<(), PeachError> {
+    check_publish_rate()?;
     let mut command = sbotcli_command();
     let output = command.arg("publish").arg("post").arg(msg).output()?;
     if output.status.success() {
+        record_publish();
         Ok(())
     } else {
         let stderr = std::str::from_utf8(&output.stderr)?;
@@ -42,6 +266,24 @@ pub fn post(msg: &str) -> Result<(), PeachError> {
     }
 }
 
+/// publishes a post and returns the key of the new message,
+/// so that it can be referenced (e.g. replied to or liked) afterwards
+pub fn post_returning_key(msg: &str) -> Result<String, PeachError> {
+    check_publish_rate()?;
+    let mut command = sbotcli_command();
+    let output = command.arg("publish").arg("post").arg(msg).output()?;
+    if output.status.success() {
+        record_publish();
+        let stdout = std::str::from_utf8(&output.stdout)?;
+        parse_published_key(stdout)
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error making ssb post: {}", stderr),
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct WhoAmIValue {
     id: String,
@@ -90,6 +332,7 @@ pub fn update_pub_name(new_name: &str) -> Result<(), PeachError> {
 }
 
 pub fn private_message(msg: &str, recipient: &str) -> Result<(), PeachError> {
+    check_publish_rate()?;
     let mut command = sbotcli_command();
     let output = command
         .arg("publish")
@@ -99,6 +342,7 @@ pub fn private_message(msg: &str, recipient: &str) -> Result<(), PeachError> {
         .arg(msg)
         .output()?;
     if output.status.success() {
+        record_publish();
         Ok(())
     } else {
         let stderr = std::str::from_utf8(&output.stderr)?;
@@ -107,3 +351,554 @@ pub fn private_message(msg: &str, recipient: &str) -> Result<(), PeachError> {
         })
     }
 }
+
+/// returns an error unless the given string is an ssb feed id (`@<base64 key>.ed25519`)
+pub fn validate_feed_id(feed_id: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^@[A-Za-z0-9+/]{43}=\.ed25519$")?;
+    if re.is_match(feed_id) {
+        Ok(())
+    } else {
+        Err(PeachError::InvalidSsbId {
+            id: feed_id.to_string(),
+        })
+    }
+}
+
+/// returns an error unless the given string is a multiserver address
+/// of the form `net:<host>:<port>~shs:<base64 key>`
+pub fn validate_multiserver_address(multiserver_addr: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^net:[^:~]+:[0-9]+~shs:[A-Za-z0-9+/]{43}=$")?;
+    if re.is_match(multiserver_addr) {
+        Ok(())
+    } else {
+        Err(PeachError::InvalidMultiserverAddress {
+            address: multiserver_addr.to_string(),
+        })
+    }
+}
+
+pub fn add_gossip_peer(multiserver_addr: &str) -> Result<(), PeachError> {
+    validate_multiserver_address(multiserver_addr)?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("call")
+        .arg("gossip.add")
+        .arg(multiserver_addr)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error adding gossip peer: {}", stderr),
+        })
+    }
+}
+
+pub fn remove_gossip_peer(id: &str) -> Result<(), PeachError> {
+    validate_feed_id(id)?;
+    let mut command = sbotcli_command();
+    let output = command.arg("call").arg("gossip.remove").arg(id).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error removing gossip peer: {}", stderr),
+        })
+    }
+}
+
+/// an ssb room which this pub is registered with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Room {
+    pub id: String,
+    pub name: String,
+    pub online: bool,
+}
+
+/// returns true if sbotcli's stderr shows it does not recognise the subcommand,
+/// which happens when the installed version predates the feature
+fn is_unsupported_command(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("no help topic") || stderr.contains("unknown command")
+}
+
+pub fn register_room(room_addr: &str) -> Result<(), PeachError> {
+    validate_multiserver_address(room_addr)?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("room")
+        .arg("register")
+        .arg(room_addr)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        if is_unsupported_command(stderr) {
+            return Err(PeachError::Unsupported {
+                feature: "rooms".to_string(),
+            });
+        }
+        Err(PeachError::SbotCliError {
+            msg: format!("Error registering room: {}", stderr),
+        })
+    }
+}
+
+pub fn list_rooms() -> Result<Vec<Room>, PeachError> {
+    let mut command = sbotcli_command();
+    let output = command.arg("room").arg("list").output()?;
+    if output.status.success() {
+        let text_output = std::str::from_utf8(&output.stdout)?;
+        let rooms: Vec<Room> = serde_json::from_str(text_output)?;
+        Ok(rooms)
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        if is_unsupported_command(stderr) {
+            return Err(PeachError::Unsupported {
+                feature: "rooms".to_string(),
+            });
+        }
+        Err(PeachError::SbotCliError {
+            msg: format!("Error listing rooms: {}", stderr),
+        })
+    }
+}
+
+/// returns the sequence number of the latest message in the given feed,
+/// or 0 if the feed has no messages yet
+pub fn latest_sequence(feed_id: &str) -> Result<u64, PeachError> {
+    validate_feed_id(feed_id)?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("hist")
+        .arg("--reverse")
+        .arg("--limit")
+        .arg("1")
+        .arg(feed_id)
+        .output()?;
+    if !output.status.success() {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error reading feed history: {}", stderr),
+        });
+    }
+    let text_output = std::str::from_utf8(&output.stdout)?;
+    match text_output.lines().find(|line| !line.trim().is_empty()) {
+        Some(line) => {
+            let msg: serde_json::Value = serde_json::from_str(line)?;
+            msg["value"]["sequence"]
+                .as_u64()
+                .ok_or_else(|| PeachError::SbotCliError {
+                    msg: format!("Message has no sequence number: {}", line),
+                })
+        }
+        // a feed with no messages has no history
+        None => Ok(0),
+    }
+}
+
+/// returns an error unless the given string looks like an invite code
+/// of the form `<host>:<port>:<pubkey>~<seed>`
+pub fn validate_invite_code(invite_code: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^[^:\s]+:[0-9]+:[^~\s]*~[^~\s]+$")?;
+    if re.is_match(invite_code) {
+        Ok(())
+    } else {
+        Err(PeachError::InvalidInviteCode {
+            code: invite_code.to_string(),
+        })
+    }
+}
+
+pub fn redeem_invite(invite_code: &str) -> Result<(), PeachError> {
+    validate_invite_code(invite_code)?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("invite")
+        .arg("accept")
+        .arg(invite_code)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        // sbotcli reports used or expired invites on stderr
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error redeeming invite: {}", stderr),
+        })
+    }
+}
+
+/// a decrypted private message addressed to this pub
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivateMessage {
+    pub author: String,
+    pub text: String,
+    /// the time the message was published, in milliseconds since the unix epoch
+    pub timestamp: i64,
+}
+
+/// returns up to `limit` of the most recent private messages addressed to this pub.
+/// messages which cannot be decrypted or parsed are skipped.
+pub fn read_private_messages(limit: u32) -> Result<Vec<PrivateMessage>, PeachError> {
+    let mut command = sbotcli_command();
+    command
+        .arg("private")
+        .arg("read")
+        .arg("--limit")
+        .arg(limit.to_string());
+    let output = output_bounded(&mut command, MAX_OUTPUT_LINES, MAX_OUTPUT_BYTES)?;
+    if !output.truncated && !output.status.success() {
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error reading private messages: {}", output.stderr),
+        });
+    }
+    let messages = output
+        .lines
+        .iter()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|msg| {
+            let value = &msg["value"];
+            // undecryptable messages have their content left as an encrypted string
+            Some(PrivateMessage {
+                author: value["author"].as_str()?.to_string(),
+                text: value["content"]["text"].as_str()?.to_string(),
+                timestamp: value["timestamp"].as_f64()? as i64,
+            })
+        })
+        .take(limit as usize)
+        .collect();
+    Ok(messages)
+}
+
+/// returns true if the file starts with the signature of a png, jpeg, gif or webp image
+fn is_image_file(path: &str) -> Result<bool, PeachError> {
+    let mut header = [0u8; 12];
+    let mut file = File::open(path)?;
+    let len = file.read(&mut header)?;
+    let header = &header[..len];
+    Ok(header.starts_with(b"\x89PNG\r\n\x1a\n")
+        || header.starts_with(b"\xff\xd8\xff")
+        || header.starts_with(b"GIF87a")
+        || header.starts_with(b"GIF89a")
+        || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP")))
+}
+
+/// adds the image at the given path as a blob and publishes an about message
+/// setting it as the image (avatar) of this pub
+pub fn set_pub_image(blob_path: &str) -> Result<(), PeachError> {
+    if !std::path::Path::new(blob_path).is_file() || !is_image_file(blob_path)? {
+        return Err(PeachError::InvalidImage {
+            path: blob_path.to_string(),
+        });
+    }
+    let mut command = sbotcli_command();
+    let output = command.arg("blobs").arg("add").arg(blob_path).output()?;
+    if !output.status.success() {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error adding image blob: {}", stderr),
+        });
+    }
+    let blob_id = std::str::from_utf8(&output.stdout)?.trim().to_string();
+    let pub_ssb_id = whoami()?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("publish")
+        .arg("about")
+        .arg("--image")
+        .arg(blob_id)
+        .arg(pub_ssb_id)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error updating pub image: {}", stderr),
+        })
+    }
+}
+
+/// an invite code created by this pub, split into its parts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invite {
+    pub code: String,
+    pub host: String,
+    pub port: u16,
+    pub uses: i32,
+}
+
+/// parses an invite code of the form `<host>:<port>:<pubkey>~<seed>`,
+/// returning an error if any part is missing or malformed
+pub fn parse_invite(invite_code: &str, uses: i32) -> Result<Invite, PeachError> {
+    validate_invite_code(invite_code)?;
+    let invalid = || PeachError::InvalidInviteCode {
+        code: invite_code.to_string(),
+    };
+    let mut parts = invite_code.splitn(3, ':');
+    let host = parts.next().ok_or_else(invalid)?;
+    let port = parts
+        .next()
+        .and_then(|port| port.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok(Invite {
+        code: invite_code.to_string(),
+        host: host.to_string(),
+        port,
+        uses,
+    })
+}
+
+/// creates an invite with the given number of uses and returns it parsed into an `Invite`
+pub fn create_invite_parsed(uses: i32) -> Result<Invite, PeachError> {
+    let invite_code = create_invite(uses)?;
+    parse_invite(&invite_code, uses)
+}
+
+/// returns the ids of the feeds followed by the given feed, or by this pub if `feed_id` is empty
+pub fn following(feed_id: &str) -> Result<Vec<String>, PeachError> {
+    let feed_id = if feed_id.is_empty() {
+        whoami()?
+    } else {
+        validate_feed_id(feed_id)?;
+        feed_id.to_string()
+    };
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("friends")
+        .arg("following")
+        .arg(&feed_id)
+        .output()?;
+    if !output.status.success() {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error reading follows: {}", stderr),
+        });
+    }
+    let text_output = std::str::from_utf8(&output.stdout)?.trim();
+    // a feed which follows no one has no output
+    if text_output.is_empty() {
+        return Ok(Vec::new());
+    }
+    let follows: Vec<String> = serde_json::from_str(text_output)?;
+    Ok(follows)
+}
+
+/// returns the latest name assigned to the given feed, either by the feed itself
+/// or by this pub, or None if the feed has not been named
+pub fn name_for_id(feed_id: &str) -> Result<Option<String>, PeachError> {
+    validate_feed_id(feed_id)?;
+    let local_id = whoami()?;
+    let mut command = sbotcli_command();
+    command.arg("bytype").arg("about");
+    let output = output_bounded(&mut command, MAX_OUTPUT_LINES, MAX_OUTPUT_BYTES)?;
+    if !output.truncated && !output.status.success() {
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error reading about messages: {}", output.stderr),
+        });
+    }
+    let latest_name = output
+        .lines
+        .iter()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|msg| {
+            let value = &msg["value"];
+            let author = value["author"].as_str()?;
+            if value["content"]["about"].as_str()? != feed_id
+                || (author != feed_id && author != local_id)
+            {
+                return None;
+            }
+            let name = value["content"]["name"].as_str()?.to_string();
+            let timestamp = value["timestamp"].as_f64()?;
+            Some((timestamp, name))
+        })
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, name)| name);
+    Ok(latest_name)
+}
+
+/// exports every message in this pub's feed to the given file, one json message per line,
+/// for backup and disaster recovery.
+/// the feed is written to a temporary file next to the output file, which only replaces
+/// the output file once the export has succeeded, so a failed export never leaves a
+/// partial backup in place of an existing one.
+pub fn export_feed(output_path: &str) -> Result<(), PeachError> {
+    let path = Path::new(output_path);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    // opening the temporary file first checks that the output directory is writable
+    let output_file = File::create(&temp_path).map_err(|err| PeachError::StdIoError {
+        source: err,
+        msg: format!("Cannot write feed export to {}", output_path),
+    })?;
+    let result = write_feed_export(output_file).and_then(|_| {
+        std::fs::rename(&temp_path, path).map_err(|err| PeachError::StdIoError {
+            source: err,
+            msg: format!("Cannot write feed export to {}", output_path),
+        })
+    });
+    if result.is_err() {
+        if let Err(err) = std::fs::remove_file(&temp_path) {
+            warn!(target: LOG_TARGET, "Failed to remove partial feed export: {}", err);
+        }
+    }
+    result
+}
+
+/// helper function which writes every message in this pub's feed to the given file
+fn write_feed_export(output_file: File) -> Result<(), PeachError> {
+    let pub_ssb_id = whoami()?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("hist")
+        .arg(pub_ssb_id)
+        .stdout(Stdio::from(output_file))
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        let hint = if stderr.to_lowercase().contains("no space left") {
+            " (insufficient disk space for the export)"
+        } else {
+            ""
+        };
+        Err(PeachError::SbotCliError {
+            msg: format!("Error exporting feed{}: {}", hint, stderr),
+        })
+    }
+}
+
+/// returns an error unless the given string is an ssb message id (`%<base64 hash>.sha256`)
+pub fn validate_message_id(msg_id: &str) -> Result<(), PeachError> {
+    let re = Regex::new(r"^%[A-Za-z0-9+/]{43}=\.sha256$")?;
+    if re.is_match(msg_id) {
+        Ok(())
+    } else {
+        Err(PeachError::InvalidSsbId {
+            id: msg_id.to_string(),
+        })
+    }
+}
+
+/// returns the key of the message published by sbotcli, found in its stdout
+fn parse_published_key(stdout: &str) -> Result<String, PeachError> {
+    let re = Regex::new(r"%[A-Za-z0-9+/]{43}=\.sha256")?;
+    match re.find(stdout) {
+        Some(key) => Ok(key.as_str().to_string()),
+        None => Err(PeachError::SbotCliError {
+            msg: format!("Could not find published message key in output: {}", stdout),
+        }),
+    }
+}
+
+/// publishes a post with an optional channel and mention links to feeds or messages,
+/// returning the key of the new message
+pub fn post_ex(text: &str, channel: Option<&str>, mentions: &[&str]) -> Result<String, PeachError> {
+    let mut mention_links = Vec::new();
+    for mention in mentions {
+        if mention.starts_with('%') {
+            validate_message_id(mention)?;
+        } else {
+            validate_feed_id(mention)?;
+        }
+        mention_links.push(serde_json::json!({ "link": mention }));
+    }
+    let mut content = serde_json::json!({
+        "type": "post",
+        "text": text,
+    });
+    if let Some(channel) = channel {
+        content["channel"] = serde_json::Value::from(channel);
+    }
+    if !mention_links.is_empty() {
+        content["mentions"] = serde_json::Value::from(mention_links);
+    }
+    check_publish_rate()?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("publish")
+        .arg("raw")
+        .arg(content.to_string())
+        .output()?;
+    if output.status.success() {
+        record_publish();
+        let stdout = std::str::from_utf8(&output.stdout)?;
+        parse_published_key(stdout)
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error making ssb post: {}", stderr),
+        })
+    }
+}
+
+/// publishes a contact message following the given feed
+pub fn follow(feed_id: &str) -> Result<(), PeachError> {
+    validate_feed_id(feed_id)?;
+    let mut command = sbotcli_command();
+    let output = command
+        .arg("publish")
+        .arg("contact")
+        .arg("--following")
+        .arg(feed_id)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        Err(PeachError::SbotCliError {
+            msg: format!("Error following {}: {}", feed_id, stderr),
+        })
+    }
+}
+
+/// follows each of the ssb admins in the peach config, so that their messages
+/// (and private messages to them, such as password resets) are replicated.
+/// every admin is attempted even if following one fails, and the failures are returned together
+pub fn follow_admins() -> Result<(), PeachError> {
+    let peach_config = load_peach_config()?;
+    let failures: Vec<String> = peach_config
+        .ssb_admin_ids
+        .iter()
+        .filter_map(|ssb_id| follow(ssb_id).err().map(|err| err.to_string()))
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PeachError::SbotCliError {
+            msg: format!("Error following ssb admins: {}", failures.join("; ")),
+        })
+    }
+}
+
+/// returns the version reported by the running sbot
+pub fn version() -> Result<String, PeachError> {
+    let mut command = sbotcli_command();
+    let output = command.arg("call").arg("version").output()?;
+    if !output.status.success() {
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        return Err(PeachError::SbotCliError {
+            msg: format!("Error getting sbot version: {}", stderr),
+        });
+    }
+    let text_output = std::str::from_utf8(&output.stdout)?.trim();
+    // the version may be returned as a json string, an object with a version field, or plain text
+    let version = match serde_json::from_str::<serde_json::Value>(text_output) {
+        Ok(serde_json::Value::String(version)) => version,
+        Ok(serde_json::Value::Object(fields)) => match fields.get("version") {
+            Some(serde_json::Value::String(version)) => version.to_string(),
+            _ => text_output.to_string(),
+        },
+        _ => text_output.to_string(),
+    };
+    Ok(version)
+}
@@ -0,0 +1,56 @@
+//! Addresses of the PeachCloud microservices.
+//!
+//! Each client reads the address of its microservice from an environment
+//! variable, falling back to a default. `ServerAddresses` collects these in one
+//! place, so that all endpoints can be inspected or overridden together (for
+//! example to point the clients at test servers).
+
+use std::env;
+
+/// Default address of the `peach-network` microservice.
+pub const DEFAULT_NETWORK_SERVER: &str = "127.0.0.1:5110";
+/// Default address of the `peach-oled` microservice.
+pub const DEFAULT_OLED_SERVER: &str = "127.0.0.1:5112";
+/// Default address of the `peach-stats` microservice.
+pub const DEFAULT_STATS_SERVER: &str = "127.0.0.1:5113";
+/// Default url of the `peach-dyndns-server`, which runs on the peach-vps.
+pub const DEFAULT_DYNDNS_SERVER: &str = "http://dynserver.dyn.peachcloud.org";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerAddresses {
+    /// Address (`host:port`) of the `peach-network` microservice.
+    pub network: String,
+    /// Address (`host:port`) of the `peach-oled` microservice.
+    pub oled: String,
+    /// Address (`host:port`) of the `peach-stats` microservice.
+    pub stats: String,
+    /// Url of the `peach-dyndns-server`.
+    pub dyndns: String,
+}
+
+impl ServerAddresses {
+    /// Reads the addresses from the `PEACH_NETWORK_SERVER`, `PEACH_OLED_SERVER`,
+    /// `PEACH_STATS_SERVER` and `PEACH_DYNDNS_SERVER` environment variables,
+    /// using the default address for any which are not set.
+    pub fn from_env() -> ServerAddresses {
+        let var =
+            |name: &str, default: &str| env::var(name).unwrap_or_else(|_| default.to_string());
+        ServerAddresses {
+            network: var("PEACH_NETWORK_SERVER", DEFAULT_NETWORK_SERVER),
+            oled: var("PEACH_OLED_SERVER", DEFAULT_OLED_SERVER),
+            stats: var("PEACH_STATS_SERVER", DEFAULT_STATS_SERVER),
+            dyndns: var("PEACH_DYNDNS_SERVER", DEFAULT_DYNDNS_SERVER),
+        }
+    }
+}
+
+impl Default for ServerAddresses {
+    fn default() -> ServerAddresses {
+        ServerAddresses {
+            network: DEFAULT_NETWORK_SERVER.to_string(),
+            oled: DEFAULT_OLED_SERVER.to_string(),
+            stats: DEFAULT_STATS_SERVER.to_string(),
+            dyndns: DEFAULT_DYNDNS_SERVER.to_string(),
+        }
+    }
+}
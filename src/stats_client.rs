@@ -6,14 +6,29 @@
 //! microservice and returns the response to the caller. These convenience
 //! methods simplify the process of performing RPC calls from other modules.
 
-use std::env;
-
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use jsonrpc_client_core::{expand_params, jsonrpc_client};
-use jsonrpc_client_http::HttpTransport;
+use jsonrpc_client_http::HttpHandle;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 
-use crate::error::PeachError;
+use crate::error::{rpc_error, PeachError};
+use crate::network_client::{self, PeachNetworkClient};
+use crate::server_addresses::ServerAddresses;
+use crate::transport::http_transport;
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::stats=debug`.
+const LOG_TARGET: &str = "peach_lib::stats";
+
+/// The name of the microservice called by this client, used in error messages.
+const SERVICE: &str = "peach-stats";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CpuStat {
@@ -55,7 +70,39 @@ pub struct MemStat {
     pub used: u64,
 }
 
+/// A running process, as reported by the `peach-stats` `process_list` method.
+///
+/// On a kernel which restricts access to other users' processes (for example
+/// with `hidepid` set on `/proc`), `cpu_percent` and `mem_bytes` may be zero
+/// for processes which the microservice is not permitted to inspect.
 #[derive(Debug, Deserialize, Serialize)]
+pub struct ProcInfo {
+    pub pid: u32,
+    pub name: String,
+    pub cpu_percent: f32,
+    pub mem_bytes: u64,
+}
+
+/// The order in which `top_processes` returns processes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// Highest CPU usage first.
+    Cpu,
+    /// Highest memory usage first.
+    Memory,
+}
+
+/// A warning that memory usage is above a threshold, returned by `check_memory_pressure`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MemAlert {
+    /// The percentage of memory in use.
+    pub used_percent: f32,
+    pub threshold_percent: u8,
+    /// The name of the process using the most memory, if it is known.
+    pub top_consumer: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Traffic {
     pub received: u64,
     pub transmitted: u64,
@@ -63,25 +110,63 @@ pub struct Traffic {
     pub tx_unit: Option<String>,
 }
 
+impl Traffic {
+    /// Sums the `received` and `transmitted` counters of the given traffic. The
+    /// units of the total are only set if every item reports the same units.
+    pub(crate) fn sum(traffic: &[&Traffic]) -> Traffic {
+        let mut total = Traffic::default();
+        let mut units = traffic.iter().map(|t| (&t.rx_unit, &t.tx_unit));
+        if let Some((rx_unit, tx_unit)) = units.next() {
+            if units.all(|(rx, tx)| rx == rx_unit && tx == tx_unit) {
+                total.rx_unit = rx_unit.clone();
+                total.tx_unit = tx_unit.clone();
+            }
+        }
+        for t in traffic {
+            total.received += t.received;
+            total.transmitted += t.transmitted;
+        }
+        total
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Uptime {
     pub secs: u64,
     pub nanos: u32,
 }
 
+/// Creates a JSON-RPC client with http transport for the `peach-stats`
+/// microservice, at the address given by the `PEACH_STATS_SERVER` environment
+/// variable (`127.0.0.1:5113` by default).
+pub fn stats_client() -> std::result::Result<PeachStatsClient<HttpHandle>, PeachError> {
+    stats_client_with(&ServerAddresses::from_env())
+}
+
+/// Creates a JSON-RPC client with http transport for the `peach-stats`
+/// microservice at the address given in `addresses`.
+pub fn stats_client_with(
+    addresses: &ServerAddresses,
+) -> std::result::Result<PeachStatsClient<HttpHandle>, PeachError> {
+    debug!(target: LOG_TARGET, "Creating HTTP transport for stats client.");
+    let transport = http_transport(None)?;
+    let http_server = format!("http://{}", addresses.stats);
+    debug!(target: LOG_TARGET, "Creating HTTP transport handle on {}.", http_server);
+    let transport_handle = transport.handle(&http_server)?;
+    info!(target: LOG_TARGET, "Creating client for peach_stats service.");
+
+    Ok(PeachStatsClient::new(transport_handle))
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-stats`
 /// `cpu_stats_percent` method.
 pub fn cpu_stats_percent() -> std::result::Result<CpuStatPercentages, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
 
-    let response = client.cpu_stats_percent().call()?;
+    let response = client
+        .cpu_stats_percent()
+        .call()
+        .map_err(rpc_error(SERVICE))?;
     let c: CpuStatPercentages = serde_json::from_str(&response)?;
 
     Ok(c)
@@ -90,16 +175,9 @@ pub fn cpu_stats_percent() -> std::result::Result<CpuStatPercentages, PeachError
 /// Creates a JSON-RPC client with http transport and calls the `peach-stats`
 /// `disk_usage` method.
 pub fn disk_usage() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
 
-    let response = client.disk_usage().call()?;
+    let response = client.disk_usage().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -107,16 +185,9 @@ pub fn disk_usage() -> std::result::Result<String, PeachError> {
 /// Creates a JSON-RPC client with http transport and calls the `peach-stats`
 /// `cpu_stats_percent` method.
 pub fn load_average() -> std::result::Result<LoadAverage, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
 
-    let response = client.load_average().call()?;
+    let response = client.load_average().call().map_err(rpc_error(SERVICE))?;
     let l: LoadAverage = serde_json::from_str(&response)?;
 
     Ok(l)
@@ -125,34 +196,243 @@ pub fn load_average() -> std::result::Result<LoadAverage, PeachError> {
 /// Creates a JSON-RPC client with http transport and calls the `peach-stats`
 /// `cpu_stats_percent` method.
 pub fn mem_stats() -> std::result::Result<MemStat, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
 
-    let response = client.mem_stats().call()?;
+    let response = client.mem_stats().call().map_err(rpc_error(SERVICE))?;
     let m: MemStat = serde_json::from_str(&response)?;
 
     Ok(m)
 }
 
+/// Calls the `peach-stats` `mem_stats` method and returns an alert if the
+/// percentage of memory in use exceeds `threshold_percent`, or `None` if it
+/// does not. This is intended to be polled by a watchdog, to warn before the
+/// kernel starts killing processes on a low-memory device.
+pub fn check_memory_pressure(
+    threshold_percent: u8,
+) -> std::result::Result<Option<MemAlert>, PeachError> {
+    let mem = mem_stats()?;
+    if mem.total == 0 {
+        return Ok(None);
+    }
+    let used_percent = mem.used as f32 / mem.total as f32 * 100.0;
+    if used_percent <= f32::from(threshold_percent) {
+        return Ok(None);
+    }
+
+    Ok(Some(MemAlert {
+        used_percent,
+        threshold_percent,
+        // the hint is best-effort, so failing to list processes does not prevent the alert
+        top_consumer: top_processes(SortKey::Memory, 1)
+            .ok()
+            .and_then(|processes| processes.into_iter().next())
+            .map(|process| process.name),
+    }))
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-stats`
+/// `process_list` method. Returns at most `limit` processes, sorted by CPU or
+/// memory usage in descending order.
+pub fn top_processes(by: SortKey, limit: u32) -> std::result::Result<Vec<ProcInfo>, PeachError> {
+    let mut client = stats_client()?;
+
+    let response = client.process_list().call().map_err(rpc_error(SERVICE))?;
+    let mut processes: Vec<ProcInfo> = serde_json::from_str(&response)?;
+    match by {
+        SortKey::Cpu => processes.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent)),
+        SortKey::Memory => processes.sort_by_key(|process| std::cmp::Reverse(process.mem_bytes)),
+    }
+    processes.truncate(limit as usize);
+
+    Ok(processes)
+}
+
+/// A value which can be read repeatedly by `sample`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    /// CPU usage in percent (user, system and nice time).
+    CpuPercent,
+    /// Memory usage in percent.
+    MemPercent,
+    /// One minute load average.
+    LoadAverage,
+    /// Signal strength (dBm) of the given interface.
+    Rssi(String),
+    /// Signal quality (%) of the given interface.
+    RssiPercent(String),
+}
+
+/// Reads a single value of `metric`, creating the client it needs on first use.
+fn read_metric(
+    metric: &Metric,
+    stats: &mut Option<PeachStatsClient<HttpHandle>>,
+    network: &mut Option<PeachNetworkClient<HttpHandle>>,
+) -> std::result::Result<f64, PeachError> {
+    let parse = |response: String| -> std::result::Result<f64, PeachError> {
+        Ok(serde_json::from_str::<f64>(response.trim())?)
+    };
+    match metric {
+        Metric::CpuPercent | Metric::MemPercent | Metric::LoadAverage => {
+            if stats.is_none() {
+                *stats = Some(stats_client()?);
+            }
+            let client = stats.as_mut().expect("stats client was just created");
+            match metric {
+                Metric::CpuPercent => {
+                    let response = client
+                        .cpu_stats_percent()
+                        .call()
+                        .map_err(rpc_error(SERVICE))?;
+                    let c: CpuStatPercentages = serde_json::from_str(&response)?;
+                    Ok(f64::from(c.user + c.system + c.nice))
+                }
+                Metric::MemPercent => {
+                    let response = client.mem_stats().call().map_err(rpc_error(SERVICE))?;
+                    let m: MemStat = serde_json::from_str(&response)?;
+                    Ok(m.used as f64 / m.total.max(1) as f64 * 100.0)
+                }
+                _ => {
+                    let response = client.load_average().call().map_err(rpc_error(SERVICE))?;
+                    let l: LoadAverage = serde_json::from_str(&response)?;
+                    Ok(f64::from(l.one))
+                }
+            }
+        }
+        Metric::Rssi(iface) | Metric::RssiPercent(iface) => {
+            if network.is_none() {
+                *network = Some(network_client::network_client()?);
+            }
+            let client = network.as_mut().expect("network client was just created");
+            let request = match metric {
+                Metric::Rssi(_) => client.rssi(iface),
+                _ => client.rssi_percent(iface),
+            };
+            parse(request.call().map_err(rpc_error("peach-network"))?)
+        }
+    }
+}
+
+/// Reads `metric` `samples` times, waiting `interval` between each reading,
+/// and returns the values along with the time each was read. The clients are
+/// reused for every reading. If a microservice stops responding part way
+/// through, the samples read so far are returned; an error is only returned if
+/// the first reading fails.
+pub fn sample(
+    metric: Metric,
+    samples: u32,
+    interval: Duration,
+) -> std::result::Result<Vec<(DateTime<Utc>, f64)>, PeachError> {
+    let mut stats = None;
+    let mut network = None;
+    let mut series = Vec::with_capacity(samples as usize);
+    for i in 0..samples {
+        if i > 0 {
+            thread::sleep(interval);
+        }
+        match read_metric(&metric, &mut stats, &mut network) {
+            Ok(value) => series.push((Utc::now(), value)),
+            Err(err) if series.is_empty() => return Err(err),
+            Err(err) => {
+                debug!(target: LOG_TARGET, "Stopped sampling {:?} after {} samples: {}", metric, series.len(), err);
+                break;
+            }
+        }
+    }
+
+    Ok(series)
+}
+
+/// The go-sbot data directory, which holds the ssb log and blobs.
+pub const SBOT_DATA_DIR: &str = "/home/peach-go-sbot/.ssb-go";
+
+/// Returns the total size in bytes of the files under `dir`, not following
+/// symlinks. A missing directory has a size of 0.
+fn dir_size(dir: &Path) -> std::result::Result<u64, PeachError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(PeachError::StdIoError {
+                source: err,
+                msg: format!("Failed to read {}", dir.display()),
+            })
+        }
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Returns the disk space in bytes used by the go-sbot data directory
+/// (`/home/peach-go-sbot/.ssb-go`), which grows without bound as the ssb log
+/// is replicated. The size is measured by summing the files in the directory
+/// on the device, so the calling user must be able to read it. If the
+/// directory does not exist, 0 is returned.
+pub fn sbot_disk_usage() -> std::result::Result<u64, PeachError> {
+    dir_size(Path::new(SBOT_DATA_DIR))
+}
+
+/// Key of the entry in the `all_traffic` map which sums every interface.
+pub const TOTAL_TRAFFIC_KEY: &str = "total";
+
+/// Returns the traffic for every network interface on the device, keyed by
+/// interface name, along with a `total` entry summing the received and
+/// transmitted values across all of them. The per-interface values come from
+/// the `peach-network` `traffic` method, using a single client for all calls.
+///
+/// Interfaces which report no traffic counters are included with zeroed
+/// `Traffic`, but any other error (such as `peach-network` not running) is
+/// returned. The units of the total are only set if every interface reports
+/// the same units.
+pub fn all_traffic() -> std::result::Result<HashMap<String, Traffic>, PeachError> {
+    let mut client = network_client::network_client()?;
+    let interfaces = network_client::list_interfaces_with(&mut client)?;
+
+    let mut all_traffic = HashMap::new();
+    for iface in interfaces {
+        let traffic = match network_client::traffic_with(&mut client, &iface) {
+            Ok(traffic) => traffic,
+            Err(PeachError::NoTrafficData { .. }) => {
+                debug!(target: LOG_TARGET, "No traffic counters for {}", iface);
+                Traffic::default()
+            }
+            Err(err) => return Err(err),
+        };
+        all_traffic.insert(iface, traffic);
+    }
+
+    let total = Traffic::sum(&all_traffic.values().collect::<Vec<_>>());
+    all_traffic.insert(TOTAL_TRAFFIC_KEY.to_string(), total);
+
+    Ok(all_traffic)
+}
+
 /// Creates a JSON-RPC client with http transport and calls the `peach-stats`
 /// `ping` method.
 pub fn ping() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
+
+    let response = client.ping().call().map_err(rpc_error(SERVICE))?;
+
+    Ok(response)
+}
+
+/// Creates a JSON-RPC client with http transport and calls the `peach-stats`
+/// `version` method.
+pub fn version() -> std::result::Result<String, PeachError> {
+    let mut client = stats_client()?;
 
-    let response = client.ping().call()?;
+    let response = client.version().call().map_err(rpc_error(SERVICE))?;
 
     Ok(response)
 }
@@ -161,16 +441,9 @@ pub fn ping() -> std::result::Result<String, PeachError> {
 /// `uptime` method. If a successful response is returned, the uptime value (in
 /// seconds) is converted to minutes before being returned to the caller.
 pub fn uptime() -> std::result::Result<String, PeachError> {
-    debug!("Creating HTTP transport for stats client.");
-    let transport = HttpTransport::new().standalone()?;
-    let http_addr = env::var("PEACH_STATS_SERVER").unwrap_or_else(|_| "127.0.0.1:5113".to_string());
-    let http_server = format!("http://{}", http_addr);
-    debug!("Creating HTTP transport handle on {}.", http_server);
-    let transport_handle = transport.handle(&http_server)?;
-    info!("Creating client for peach_stats service.");
-    let mut client = PeachStatsClient::new(transport_handle);
+    let mut client = stats_client()?;
 
-    let response = client.uptime().call()?;
+    let response = client.uptime().call().map_err(rpc_error(SERVICE))?;
     let u: Uptime = serde_json::from_str(&response)?;
     let minutes = (u.secs / 60).to_string();
 
@@ -193,6 +466,12 @@ jsonrpc_client!(pub struct PeachStatsClient {
     /// JSON-RPC request to check availability of the `peach-stats` microservice.
     pub fn ping(&mut self) -> RpcRequest<String>;
 
+    /// JSON-RPC request to get a list of running processes.
+    pub fn process_list(&mut self) -> RpcRequest<String>;
+
     /// JSON-RPC request to get system uptime.
     pub fn uptime(&mut self) -> RpcRequest<String>;
+
+    /// JSON-RPC request to get the version of the `peach-stats` microservice.
+    pub fn version(&mut self) -> RpcRequest<String>;
 });
@@ -0,0 +1,36 @@
+//! The http transport shared by the JSON-RPC clients.
+//!
+//! Every request made by the network, OLED, stats and dyndns clients times out
+//! after `PEACH_RPC_TIMEOUT_MS` milliseconds (5000 by default), returning
+//! `PeachError::Timeout`, so that a hung microservice cannot block the caller
+//! forever.
+
+use std::env;
+use std::time::Duration;
+
+use jsonrpc_client_http::HttpTransport;
+
+use crate::error::PeachError;
+
+/// Default timeout for JSON-RPC requests, in milliseconds.
+pub const DEFAULT_RPC_TIMEOUT_MS: u64 = 5000;
+
+/// Returns the timeout for JSON-RPC requests, read (in milliseconds) from the
+/// `PEACH_RPC_TIMEOUT_MS` environment variable, or `DEFAULT_RPC_TIMEOUT_MS`
+/// if it is not set or not a number.
+pub fn rpc_timeout() -> Duration {
+    let ms = env::var("PEACH_RPC_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_RPC_TIMEOUT_MS);
+    Duration::from_millis(ms)
+}
+
+/// Creates an http transport whose requests time out after the given duration,
+/// or after `rpc_timeout()` if none is given.
+pub(crate) fn http_transport(timeout: Option<Duration>) -> Result<HttpTransport, PeachError> {
+    let timeout = timeout.unwrap_or_else(rpc_timeout);
+    let transport = HttpTransport::new().timeout(timeout).standalone()?;
+
+    Ok(transport)
+}
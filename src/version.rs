@@ -0,0 +1,49 @@
+//! Report the version of peach-lib and of the microservices it talks to.
+//!
+//! This is intended for support and debugging, so a microservice which cannot
+//! be reached is reported as having no version rather than causing an error.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PeachError;
+use crate::{network_client, oled_client, sbot_client, stats_client};
+
+/// The log target for this module, e.g. `RUST_LOG=peach_lib::version=debug`.
+const LOG_TARGET: &str = "peach_lib::version";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VersionInfo {
+    /// The version of peach-lib itself.
+    pub peach_lib: String,
+    pub network: Option<String>,
+    pub oled: Option<String>,
+    pub stats: Option<String>,
+    pub sbot: Option<String>,
+}
+
+/// Returns the version of a service, or `None` if it could not be retrieved.
+fn service_version(
+    service: &str,
+    result: std::result::Result<String, PeachError>,
+) -> Option<String> {
+    match result {
+        Ok(version) => Some(version),
+        Err(err) => {
+            debug!(target: LOG_TARGET, "Failed to get {} version: {}", service, err);
+            None
+        }
+    }
+}
+
+/// Returns the version of peach-lib, along with the versions reported by each
+/// of the network, oled, stats and sbot services which are reachable.
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        peach_lib: env!("CARGO_PKG_VERSION").to_string(),
+        network: service_version("peach-network", network_client::version()),
+        oled: service_version("peach-oled", oled_client::version()),
+        stats: service_version("peach-stats", stats_client::version()),
+        sbot: service_version("sbot", sbot_client::version()),
+    }
+}
@@ -0,0 +1,936 @@
+//! End-to-end tests of the JSON-RPC clients against a fake server.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use common::{lock_env, rpc_error, unused_addr, FakeServer, TempDir};
+use peach_lib::error::PeachError;
+use peach_lib::network_client::{ConnectionState, WifiMode};
+use peach_lib::{dyndns_client, network_client, sbot_client, stats_client, ServerAddresses};
+
+#[test]
+fn network_client_uses_server_from_env() {
+    let server = FakeServer::start(&[("ping", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let mut client = network_client::network_client().unwrap();
+
+    assert_eq!(client.ping().call().unwrap(), "success");
+}
+
+#[test]
+fn network_online_and_latency() {
+    let server = FakeServer::start(&[("ping", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert!(network_client::is_network_online());
+    assert!(network_client::ping_with_latency().unwrap() < Duration::from_secs(5));
+}
+
+#[test]
+fn network_offline_when_not_running() {
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", unused_addr());
+
+    assert!(!network_client::is_network_online());
+    assert!(network_client::ping_with_latency().is_err());
+}
+
+#[test]
+fn network_clients_can_target_different_servers() {
+    let first = FakeServer::start(&[("ping", json!("first"))]);
+    let second = FakeServer::start(&[("ping", json!("second"))]);
+    let _env = lock_env();
+
+    assert_eq!(network_client::ping_at(&first.addr).unwrap(), "first");
+    let mut client = network_client::network_client_at(&second.addr).unwrap();
+    assert_eq!(client.ping().call().unwrap(), "second");
+}
+
+#[test]
+fn network_server_defaults_to_localhost() {
+    let _env = lock_env();
+    env::remove_var("PEACH_NETWORK_SERVER");
+
+    assert_eq!(ServerAddresses::from_env().network, "127.0.0.1:5110");
+}
+
+#[test]
+fn available_networks_returns_scan_results() {
+    let networks = json!([{ "detail": null, "signal": -52, "state": "Available" }]).to_string();
+    let server = FakeServer::start(&[("available_networks", json!(networks))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let response = network_client::available_networks("wlan0").unwrap();
+
+    assert_eq!(response, networks);
+}
+
+#[test]
+fn available_networks_parsed_returns_scans() {
+    let networks = json!([{
+        "protocol": "WPA2",
+        "frequency": "2412",
+        "signal_level": "-52",
+        "ssid": "Home",
+    }]);
+    let server = FakeServer::start(&[("available_networks", json!(networks.to_string()))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let networks = network_client::available_networks_parsed("wlan0").unwrap();
+
+    assert_eq!(networks.len(), 1);
+    assert_eq!(networks[0].ssid, "Home");
+}
+
+#[test]
+fn available_networks_sorted_dedupes_by_strongest_signal() {
+    let scan = |ssid: &str, signal_level: &str| {
+        json!({
+            "protocol": "WPA2",
+            "frequency": "2412",
+            "signal_level": signal_level,
+            "ssid": ssid,
+        })
+    };
+    let networks = json!([
+        scan("Home", "-70"),
+        scan("Cafe", "-60"),
+        scan("Home", "-45"),
+        scan("Junk", "strong"),
+        scan("Cafe", "?"),
+    ]);
+    let server = FakeServer::start(&[("available_networks", json!(networks.to_string()))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let networks = network_client::available_networks_sorted("wlan0").unwrap();
+
+    let networks: Vec<_> = networks
+        .iter()
+        .map(|scan| (scan.ssid.as_str(), scan.signal_level.as_str()))
+        .collect();
+    assert_eq!(
+        networks,
+        vec![("Home", "-45"), ("Cafe", "-60"), ("Junk", "strong")]
+    );
+}
+
+#[test]
+fn malformed_available_networks_is_an_error() {
+    let server = FakeServer::start(&[("available_networks", json!("[{"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::available_networks_parsed("wlan0");
+
+    assert!(matches!(result, Err(PeachError::Serde { .. })));
+}
+
+#[test]
+fn saved_networks_parsed_keeps_the_config_order() {
+    let saved = json!([{ "ssid": "Work" }, { "ssid": "Home" }]).to_string();
+    let server = FakeServer::start(&[("saved_networks", json!(saved))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let ssids: Vec<String> = network_client::saved_networks_parsed()
+        .unwrap()
+        .into_iter()
+        .map(|network| network.ssid)
+        .collect();
+
+    assert_eq!(ssids, vec!["Work", "Home"]);
+}
+
+#[test]
+fn malformed_saved_networks_is_an_error() {
+    let server = FakeServer::start(&[("saved_networks", json!("[{ \"ssid\""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert!(matches!(
+        network_client::saved_networks_parsed(),
+        Err(PeachError::Serde { .. })
+    ));
+    assert!(matches!(
+        network_client::saved_ap("Home"),
+        Err(PeachError::Serde { .. })
+    ));
+}
+
+#[test]
+fn saved_ap_returns_transport_errors() {
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", unused_addr());
+
+    assert!(matches!(
+        network_client::saved_ap("Home"),
+        Err(PeachError::ServiceUnavailable { .. })
+    ));
+}
+
+#[test]
+fn traffic_is_parsed() {
+    let traffic = json!({
+        "received": 1024,
+        "transmitted": 2048,
+        "rx_unit": "KB",
+        "tx_unit": "KB",
+    });
+    let server = FakeServer::start(&[("traffic", json!(traffic.to_string()))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let traffic = network_client::traffic("wlan0").unwrap();
+
+    assert_eq!(traffic.received, 1024);
+    assert_eq!(traffic.transmitted, 2048);
+    assert_eq!(traffic.rx_unit.as_deref(), Some("KB"));
+}
+
+#[test]
+fn malformed_traffic_is_an_error() {
+    let server = FakeServer::start(&[("traffic", json!("{ not json"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::traffic("wlan0");
+
+    assert!(matches!(result, Err(PeachError::Serde { .. })));
+}
+
+#[test]
+fn missing_traffic_is_no_traffic_data() {
+    let server = FakeServer::start(&[("traffic", json!(""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::traffic("wlan0");
+
+    assert!(matches!(result, Err(PeachError::NoTrafficData { .. })));
+}
+
+#[test]
+fn total_traffic_sums_interfaces() {
+    let traffic = json!({ "received": 100, "transmitted": 50 });
+    let server = FakeServer::start(&[("traffic", json!(traffic.to_string()))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let total = network_client::total_traffic(&["eth0", "wlan0"], false).unwrap();
+
+    assert_eq!(total.received, 200);
+    assert_eq!(total.transmitted, 100);
+}
+
+#[test]
+fn total_traffic_can_skip_failed_interfaces() {
+    let server = FakeServer::start(&[("traffic", json!(""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let total = network_client::total_traffic(&["wlan9"], true).unwrap();
+    assert_eq!(total.received, 0);
+
+    let result = network_client::total_traffic(&["wlan9"], false);
+    assert!(matches!(result, Err(PeachError::NoTrafficData { .. })));
+}
+
+#[test]
+fn total_traffic_does_not_skip_an_unavailable_service() {
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", unused_addr());
+
+    let result = network_client::total_traffic(&["wlan0"], true);
+
+    assert!(matches!(result, Err(PeachError::ServiceUnavailable { .. })));
+}
+
+#[test]
+fn total_traffic_skips_a_malformed_response() {
+    // a number where peach-network should answer with a string of json
+    let server = FakeServer::start(&[("traffic", json!(42))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::total_traffic(&["wlan0"], false);
+    match result {
+        Err(err) => assert!(!err.is_transport_error()),
+        Ok(_) => panic!("a malformed response should be an error"),
+    }
+    let total = network_client::total_traffic(&["wlan0"], true).unwrap();
+    assert_eq!(total.received, 0);
+}
+
+#[test]
+fn all_traffic_zeroes_only_interfaces_without_counters() {
+    let interfaces = json!(["wlan0", "eth0"]).to_string();
+    let server = FakeServer::start(&[
+        ("list_interfaces", json!(interfaces)),
+        ("traffic", json!("")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let all_traffic = stats_client::all_traffic().unwrap();
+    assert_eq!(all_traffic.len(), 3);
+    assert_eq!(all_traffic[stats_client::TOTAL_TRAFFIC_KEY].received, 0);
+
+    // a server without the traffic method is an error, not zero traffic
+    let server = FakeServer::start(&[("list_interfaces", json!(interfaces))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert!(stats_client::all_traffic().is_err());
+}
+
+#[test]
+fn list_interfaces_uses_the_rpc_when_supported() {
+    let interfaces = json!(["wlan0", "eth0"]).to_string();
+    let server = FakeServer::start(&[("list_interfaces", json!(interfaces))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(
+        network_client::list_interfaces().unwrap(),
+        vec!["eth0", "wlan0"]
+    );
+}
+
+#[test]
+fn list_interfaces_falls_back_to_sysfs() {
+    let sysfs = env::temp_dir().join(format!("peach-lib-sysfs-net-{}", std::process::id()));
+    for iface in &["wlan0", "eth0", "lo"] {
+        fs::create_dir_all(sysfs.join(iface)).unwrap();
+    }
+    let server = FakeServer::start(&[]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    env::set_var("PEACH_SYSFS_NET_PATH", &sysfs);
+
+    let interfaces = network_client::list_interfaces();
+    env::remove_var("PEACH_SYSFS_NET_PATH");
+    fs::remove_dir_all(&sysfs).unwrap();
+
+    assert_eq!(interfaces.unwrap(), vec!["eth0", "lo", "wlan0"]);
+}
+
+#[test]
+fn mac_address_is_read_from_sysfs() {
+    let sysfs = env::temp_dir().join(format!("peach-lib-sysfs-mac-{}", std::process::id()));
+    fs::create_dir_all(sysfs.join("wlan0")).unwrap();
+    fs::write(sysfs.join("wlan0").join("address"), "B8:27:EB:12:34:56\n").unwrap();
+    let _env = lock_env();
+    env::set_var("PEACH_SYSFS_NET_PATH", &sysfs);
+
+    let mac = network_client::mac_address("wlan0");
+    let missing = network_client::mac_address("wlan9");
+    env::remove_var("PEACH_SYSFS_NET_PATH");
+    fs::remove_dir_all(&sysfs).unwrap();
+
+    assert_eq!(mac.unwrap(), "b8:27:eb:12:34:56");
+    assert!(matches!(missing, Err(PeachError::InterfaceNotFound { .. })));
+}
+
+#[test]
+fn connection_state_is_parsed() {
+    let server = FakeServer::start(&[("state", json!("Up\n"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(
+        network_client::connection_state("wlan0").unwrap(),
+        ConnectionState::Up
+    );
+    assert_eq!(
+        ConnectionState::from("lowerlayerdown"),
+        ConnectionState::Unknown("lowerlayerdown".to_string())
+    );
+}
+
+#[test]
+fn current_mode_follows_the_ap_interface() {
+    let _env = lock_env();
+    let server = FakeServer::start(&[("state", json!("up"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(
+        network_client::current_mode().unwrap(),
+        WifiMode::AccessPoint
+    );
+    assert_eq!(server.calls()[0].1, json!(["ap0"]));
+
+    let server = FakeServer::start(&[("state", json!("down"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(network_client::current_mode().unwrap(), WifiMode::Client);
+}
+
+#[test]
+fn wifi_mode_mapping() {
+    let mode = |state: Option<ConnectionState>| WifiMode::from_ap_state(state.as_ref());
+
+    assert_eq!(mode(Some(ConnectionState::Up)), WifiMode::AccessPoint);
+    assert_eq!(mode(Some(ConnectionState::Down)), WifiMode::Client);
+    assert_eq!(mode(Some(ConnectionState::Dormant)), WifiMode::Client);
+    assert_eq!(mode(None), WifiMode::Client);
+}
+
+#[test]
+fn update_reports_the_failed_step() {
+    let server = FakeServer::start(&[
+        ("id", json!("0")),
+        ("delete", json!("success")),
+        ("save", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::update("wlan0", "Home", "password");
+
+    match result {
+        Err(PeachError::NetworkUpdateFailed { step, report, .. }) => {
+            assert_eq!(step, "add");
+            assert!(report.deleted && report.saved);
+            assert!(!report.added && !report.reconfigured);
+        }
+        other => panic!("expected NetworkUpdateFailed, got {:?}", other),
+    }
+}
+
+#[test]
+fn adding_a_saved_network_replaces_its_credentials() {
+    let saved = json!([{ "id": "0", "ssid": "Home" }]).to_string();
+    let server = FakeServer::start(&[
+        ("saved_networks", json!(saved)),
+        ("id", json!("0")),
+        ("delete", json!("success")),
+        ("save", json!("success")),
+        ("add", json!("success")),
+        ("reconfigure", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::add("Home", "new-password").unwrap();
+
+    let calls = server.calls();
+    let adds: Vec<_> = calls.iter().filter(|(method, _)| method == "add").collect();
+    assert_eq!(adds.len(), 1);
+    assert_eq!(adds[0].1, json!(["Home", "new-password"]));
+    assert!(calls.iter().any(|(method, _)| method == "delete"));
+}
+
+#[test]
+fn add_and_connect_joins_a_new_network() {
+    let server = FakeServer::start(&[
+        ("saved_networks", json!("[]")),
+        ("add", json!("success")),
+        ("save", json!("success")),
+        ("id", json!("4")),
+        ("connect", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::add_and_connect("wlan0", "Cafe", "password").unwrap();
+
+    let calls = server.calls();
+    let methods: Vec<_> = calls.iter().map(|(method, _)| method.as_str()).collect();
+    assert_eq!(
+        methods,
+        vec!["saved_networks", "add", "save", "id", "connect"]
+    );
+    assert_eq!(calls[4].1, json!(["4", "wlan0"]));
+}
+
+#[test]
+fn add_and_connect_removes_the_network_if_connecting_fails() {
+    let server = FakeServer::start(&[
+        ("saved_networks", json!("[]")),
+        ("add", json!("success")),
+        ("save", json!("success")),
+        ("id", json!("4")),
+        ("delete", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::add_and_connect("wlan0", "Cafe", "password");
+
+    assert!(result.is_err());
+    let calls = server.calls();
+    let methods: Vec<_> = calls.iter().map(|(method, _)| method.as_str()).collect();
+    assert_eq!(
+        methods,
+        vec![
+            "saved_networks",
+            "add",
+            "save",
+            "id",
+            "connect",
+            "id",
+            "delete",
+            "save"
+        ]
+    );
+    assert_eq!(calls[6].1, json!(["wlan0", "4"]));
+}
+
+#[test]
+fn ssid_opt_is_none_when_not_associated() {
+    let server = FakeServer::start(&[("ssid", json!(""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(network_client::ssid_opt("wlan0").unwrap(), None);
+}
+
+#[test]
+fn ssid_opt_surfaces_other_errors() {
+    let server = FakeServer::start(&[]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert!(network_client::ssid_opt("wlan0").is_err());
+}
+
+#[test]
+fn ssid_opt_only_maps_not_available_errors_to_none() {
+    let server = FakeServer::start(&[(
+        "ssid",
+        rpc_error(
+            -32024,
+            "Failed to retrieve SSID for wlan0. Interface may not be connected",
+        ),
+    )]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(network_client::ssid_opt("wlan0").unwrap(), None);
+
+    let server = FakeServer::start(&[("ssid", rpc_error(-32000, "wpa_cli failed"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert!(network_client::ssid_opt("wlan0").is_err());
+}
+
+#[test]
+fn ip_addr_parses_ipv4_and_ipv6() {
+    let _env = lock_env();
+    let server = FakeServer::start(&[("ip", json!("192.168.1.20\n"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(
+        network_client::ip_addr("wlan0").unwrap(),
+        Some("192.168.1.20".parse().unwrap())
+    );
+
+    let server = FakeServer::start(&[("ip", json!("fe80::1"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(
+        network_client::ip_addr("wlan0").unwrap(),
+        Some("fe80::1".parse().unwrap())
+    );
+}
+
+#[test]
+fn ip_addr_is_none_without_an_address() {
+    let server = FakeServer::start(&[("ip", json!("\n"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(network_client::ip_addr("wlan0").unwrap(), None);
+}
+
+#[test]
+fn invalid_ip_addr_is_an_error() {
+    let server = FakeServer::start(&[("ip", json!("not an ip"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::ip_addr("wlan0");
+
+    assert!(matches!(result, Err(PeachError::InvalidIpAddress { .. })));
+}
+
+#[test]
+fn rssi_dbm_parses_the_signal() {
+    let server = FakeServer::start(&[("rssi", json!("-52"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(network_client::rssi_dbm("wlan0").unwrap(), Some(-52));
+}
+
+#[test]
+fn rssi_is_none_without_a_signal() {
+    let server = FakeServer::start(&[("rssi", json!("")), ("rssi_percent", json!(""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(network_client::rssi_dbm("wlan0").unwrap(), None);
+    assert_eq!(network_client::rssi_percentage("wlan0").unwrap(), None);
+}
+
+#[test]
+fn rssi_percentage_is_clamped() {
+    let _env = lock_env();
+    let server = FakeServer::start(&[("rssi_percent", json!("130"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(network_client::rssi_percentage("wlan0").unwrap(), Some(100));
+
+    let server = FakeServer::start(&[("rssi_percent", json!("-5"))]);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    assert_eq!(network_client::rssi_percentage("wlan0").unwrap(), Some(0));
+}
+
+#[test]
+fn malformed_rssi_is_an_error() {
+    let server = FakeServer::start(&[("rssi", json!("strong"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let result = network_client::rssi_dbm("wlan0");
+
+    assert!(matches!(
+        result,
+        Err(PeachError::InvalidSignalStrength { .. })
+    ));
+}
+
+#[test]
+fn disconnect_sends_the_interface() {
+    let server = FakeServer::start(&[("disconnect", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::disconnect("wlan0").unwrap();
+
+    assert_eq!(
+        server.calls(),
+        vec![("disconnect".to_string(), json!(["wlan0"]))]
+    );
+}
+
+#[test]
+fn modify_sets_the_password_of_the_saved_network() {
+    let server = FakeServer::start(&[("id", json!("3")), ("modify", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::modify("wlan0", "Home", "new-password").unwrap();
+
+    let calls = server.calls();
+    assert_eq!(
+        calls[1],
+        ("modify".to_string(), json!(["3", "wlan0", "new-password"]))
+    );
+}
+
+#[test]
+fn forget_sends_delete_params_in_server_order() {
+    let server = FakeServer::start(&[
+        ("id", json!("3")),
+        ("delete", json!("success")),
+        ("save", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::forget("wlan0", "Home").unwrap();
+
+    let calls = server.calls();
+    assert_eq!(calls[1], ("delete".to_string(), json!(["wlan0", "3"])));
+}
+
+#[test]
+fn helpers_can_share_a_client() {
+    let saved = json!([{ "id": "3", "ssid": "Home" }]).to_string();
+    let server = FakeServer::start(&[
+        ("saved_networks", json!(saved)),
+        ("id", json!("3")),
+        ("delete", json!("success")),
+        ("save", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let mut client = network_client::network_client().unwrap();
+    assert!(network_client::saved_ap_with(&mut client, "Home").unwrap());
+    network_client::forget_with(&mut client, "wlan0", "Home").unwrap();
+
+    let methods: Vec<_> = server
+        .calls()
+        .into_iter()
+        .map(|(method, _)| method)
+        .collect();
+    assert_eq!(methods, vec!["saved_networks", "id", "delete", "save"]);
+}
+
+#[test]
+fn set_country_code_sends_uppercase_code() {
+    let server = FakeServer::start(&[("set_country", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    network_client::set_country_code("gb").unwrap();
+
+    assert_eq!(
+        server.calls(),
+        vec![("set_country".to_string(), json!(["GB"]))]
+    );
+}
+
+#[test]
+fn invalid_country_codes_are_rejected_before_calling() {
+    let server = FakeServer::start(&[("set_country", json!("success"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    for code in &["", "G", "GBR", "G1", "é"] {
+        assert!(matches!(
+            network_client::set_country_code(code),
+            Err(PeachError::InvalidCountryCode { .. })
+        ));
+    }
+    assert!(server.calls().is_empty());
+}
+
+#[test]
+fn get_country_code_is_none_when_unset() {
+    let server = FakeServer::start(&[("get_country", json!(""))]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert_eq!(network_client::get_country_code().unwrap(), None);
+}
+
+#[test]
+fn is_domain_available_parses_bool() {
+    let server = FakeServer::start(&[("is_domain_available", json!("true"))]);
+    let _env = lock_env();
+    env::set_var("PEACH_DYNDNS_SERVER", server.url());
+
+    assert!(dyndns_client::is_domain_available("example.dyn.peachcloud.org").unwrap());
+}
+
+#[test]
+fn unreachable_dyndns_server_is_unavailable() {
+    let _env = lock_env();
+    env::set_var("PEACH_DYNDNS_SERVER", format!("http://{}", unused_addr()));
+
+    let result = dyndns_client::is_domain_available("example.dyn.peachcloud.org");
+
+    assert!(matches!(
+        result,
+        Err(PeachError::ServiceUnavailable { service }) if service == "peach-dyndns-server"
+    ));
+}
+
+#[test]
+fn unresponsive_service_times_out() {
+    // accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _env = lock_env();
+    env::set_var(
+        "PEACH_NETWORK_SERVER",
+        listener.local_addr().unwrap().to_string(),
+    );
+    env::set_var("PEACH_RPC_TIMEOUT_MS", "200");
+
+    let result = network_client::ping();
+    env::remove_var("PEACH_RPC_TIMEOUT_MS");
+
+    assert!(matches!(result, Err(PeachError::Timeout)));
+}
+
+#[test]
+fn ping_retries_until_the_service_starts() {
+    let addr = unused_addr();
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &addr);
+    env::set_var("PEACH_RPC_RETRIES", "4");
+    // the first attempts are refused, until the server comes up
+    let server_addr = addr.clone();
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(150));
+        FakeServer::start_on(&server_addr, &[("ping", json!("success"))])
+    });
+
+    let result = network_client::ping();
+    env::remove_var("PEACH_RPC_RETRIES");
+
+    assert_eq!(result.unwrap(), "success");
+    assert_eq!(server.join().unwrap().calls().len(), 1);
+}
+
+#[test]
+fn timed_out_ping_is_not_retried() {
+    // accepts connections but never answers them, counting the requests made
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let accepted = thread::spawn(move || {
+        listener.set_nonblocking(true).unwrap();
+        let mut streams = Vec::new();
+        for _ in 0..20 {
+            if let Ok((stream, _)) = listener.accept() {
+                streams.push(stream);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        streams.len()
+    });
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &addr);
+    env::set_var("PEACH_RPC_TIMEOUT_MS", "200");
+    env::set_var("PEACH_RPC_RETRIES", "3");
+
+    let result = network_client::ping();
+    env::remove_var("PEACH_RPC_TIMEOUT_MS");
+    env::remove_var("PEACH_RPC_RETRIES");
+
+    assert!(matches!(result, Err(PeachError::Timeout)));
+    assert_eq!(accepted.join().unwrap(), 1);
+}
+
+#[test]
+fn ping_is_not_retried_by_default() {
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", unused_addr());
+
+    let result = network_client::ping();
+
+    assert!(matches!(result, Err(PeachError::ServiceUnavailable { .. })));
+}
+
+#[test]
+fn errors_from_a_running_service_are_not_retried() {
+    let server = FakeServer::start(&[]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    env::set_var("PEACH_RPC_RETRIES", "3");
+
+    let result = network_client::ping();
+    env::remove_var("PEACH_RPC_RETRIES");
+
+    assert!(result.is_err());
+    assert_eq!(server.calls().len(), 1);
+}
+
+#[test]
+fn missing_method_is_an_error() {
+    let server = FakeServer::start(&[]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    assert!(network_client::traffic("wlan0").is_err());
+}
+
+#[test]
+fn output_bounded_drains_stderr_and_replaces_invalid_utf8() {
+    // more stderr than fits in a pipe buffer, which must not block the command
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("head -c 200000 /dev/zero | tr '\\0' e >&2; printf 'a\\377b\\nc\\n'");
+
+    let output = sbot_client::output_bounded(&mut command, 10, 1024).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.lines, vec!["a\u{fffd}b", "c"]);
+    assert_eq!(output.stderr.len(), 1024);
+    assert!(!output.truncated);
+}
+
+#[test]
+fn failed_feed_export_keeps_the_previous_export() {
+    let dir = TempDir::new("feed-export");
+    let export = dir.file("feed.json");
+    fs::write(&export, "previous export\n").unwrap();
+    let _env = lock_env();
+    // no sbot is running in this repo, so the export fails
+    env::set_var("PEACH_SBOT_REPO", dir.file("sbot"));
+
+    let result = sbot_client::export_feed(&export);
+    env::remove_var("PEACH_SBOT_REPO");
+
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&export).unwrap(), "previous export\n");
+    assert_eq!(fs::read_dir(&dir.path).unwrap().count(), 1);
+}
+
+#[test]
+fn rpc_contract_check_does_not_save_after_a_failed_delete() {
+    // the server has no delete method, so the scratch network cannot be removed
+    let server = FakeServer::start(&[
+        ("saved_networks", json!("")),
+        ("add", json!("success")),
+        ("id", json!("3")),
+        ("save", json!("success")),
+    ]);
+    let _env = lock_env();
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+
+    let issues = network_client::verify_rpc_contract("wlan0", "PeachContractTest").unwrap();
+
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].method, "delete");
+    let methods: Vec<String> = server
+        .calls()
+        .into_iter()
+        .map(|(method, _)| method)
+        .collect();
+    assert_eq!(
+        methods,
+        vec!["saved_networks", "add", "id", "delete", "delete"]
+    );
+}
+
+#[test]
+fn ip_change_is_detected_against_the_last_seen_ip() {
+    let dir = TempDir::new("last-seen-ip");
+    let last_seen_ip = dir.file("peach-dyndns/last_seen_ip");
+    let check = |ip: &'static str| {
+        dyndns_client::ip_changed_since_last_check_with(|| Ok(format!("{}\n", ip)), &last_seen_ip)
+            .unwrap()
+    };
+
+    // the first check only records the ip address
+    assert_eq!(check("203.0.113.1"), None);
+    assert_eq!(check("203.0.113.1"), None);
+    assert_eq!(
+        check("203.0.113.2"),
+        Some(("203.0.113.1".to_string(), "203.0.113.2".to_string()))
+    );
+    assert_eq!(fs::read_to_string(&last_seen_ip).unwrap(), "203.0.113.2");
+}
+
+#[test]
+fn failed_publish_does_not_count_towards_the_rate_limit() {
+    let dir = TempDir::new("publish-rate");
+    let _env = lock_env();
+    // no sbot is running in this repo, so publishing fails
+    env::set_var("PEACH_SBOT_REPO", dir.file("sbot"));
+    env::set_var("PEACH_SBOT_MIN_PUBLISH_INTERVAL_SECS", "60");
+    env::set_var("PEACH_SBOT_LAST_PUBLISH_FILE", dir.file("last_publish"));
+
+    let first = sbot_client::post("hello");
+    let second = sbot_client::post("hello");
+    env::remove_var("PEACH_SBOT_REPO");
+    env::remove_var("PEACH_SBOT_MIN_PUBLISH_INTERVAL_SECS");
+    env::remove_var("PEACH_SBOT_LAST_PUBLISH_FILE");
+
+    assert!(first.is_err());
+    assert!(!matches!(
+        second,
+        Err(PeachError::PublishRateLimited { .. })
+    ));
+    assert!(fs::metadata(dir.file("last_publish")).is_err());
+}
@@ -0,0 +1,180 @@
+//! A fake JSON-RPC server for testing the clients without live microservices.
+//!
+//! The server listens on a random local port and answers each JSON-RPC
+//! request with the scripted result for its method. Clients are pointed at it
+//! through the same `PEACH_*_SERVER` environment variables used in production.
+//!
+//! Also has helpers for tests which point the config and password files at a
+//! temporary directory through their `PEACH_*` path overrides.
+
+// each test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use serde_json::{json, Value};
+
+/// Tests within a binary run in parallel but share environment variables, so
+/// tests which set a server address hold this lock while they run.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn lock_env() -> MutexGuard<'static, ()> {
+    // a failed test poisons the lock, but the environment is still usable
+    ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+pub struct FakeServer {
+    /// Address (`host:port`) the server is listening on.
+    pub addr: String,
+    /// The method and params of each request received, in order.
+    calls: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl FakeServer {
+    /// Starts a server which responds to each method in `responses` with the
+    /// given result (or error, see `rpc_error`), and to any other method with a
+    /// "method not found" error.
+    pub fn start(responses: &[(&str, Value)]) -> FakeServer {
+        FakeServer::start_on("127.0.0.1:0", responses)
+    }
+
+    /// Like `start`, but listens on the given address, e.g. one reserved with
+    /// `unused_addr` for a server which is started after the client.
+    pub fn start_on(addr: &str, responses: &[(&str, Value)]) -> FakeServer {
+        let listener = TcpListener::bind(addr).expect("failed to bind fake server");
+        let addr = listener.local_addr().unwrap().to_string();
+        let responses: HashMap<String, Value> = responses
+            .iter()
+            .map(|(method, result)| (method.to_string(), result.clone()))
+            .collect();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&calls);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let responses = responses.clone();
+                let recorded = Arc::clone(&recorded);
+                thread::spawn(move || handle_connection(stream, &responses, &recorded));
+            }
+        });
+        FakeServer { addr, calls }
+    }
+
+    /// Returns the method and params of each request received so far.
+    pub fn calls(&self) -> Vec<(String, Value)> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+/// Returns a response for `FakeServer::start` which makes the server answer
+/// the method with a JSON-RPC error rather than a result.
+pub fn rpc_error(code: i64, message: &str) -> Value {
+    json!({ "$error": { "code": code, "message": message } })
+}
+
+/// Returns a local address which nothing is listening on, so that connections
+/// to it are refused.
+pub fn unused_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("failed to reserve an address");
+    listener.local_addr().unwrap().to_string()
+}
+
+/// A directory under the system temp dir which is removed when dropped.
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("peach-lib-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("failed to create temp dir");
+        TempDir { path }
+    }
+
+    /// Returns the path of a file in the directory as a string.
+    pub fn file(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Points the config, its lock and audit log, and the htpasswd file at files in
+/// `dir`. The caller must hold `lock_env`.
+pub fn use_config_dir(dir: &TempDir) {
+    env::set_var("PEACH_CONFIG_PATH", dir.file("config.yml"));
+    env::set_var("PEACH_CONFIG_LOCK_PATH", dir.file("config.lock"));
+    env::set_var("PEACH_CONFIG_AUDIT_LOG", dir.file("audit.log"));
+    env::set_var("PEACH_HTPASSWD_FILE", dir.file("htpasswd"));
+}
+
+/// Reads a single http request and writes the JSON-RPC response.
+fn handle_connection(
+    stream: TcpStream,
+    responses: &HashMap<String, Value>,
+    calls: &Mutex<Vec<(String, Value)>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let method = request["method"].as_str().unwrap_or_default();
+    calls
+        .lock()
+        .unwrap()
+        .push((method.to_string(), request["params"].clone()));
+    let response = match responses.get(method) {
+        Some(Value::Object(response)) if response.contains_key("$error") => json!({
+            "jsonrpc": "2.0",
+            "error": response["$error"],
+            "id": request["id"],
+        }),
+        Some(result) => json!({ "jsonrpc": "2.0", "result": result, "id": request["id"] }),
+        None => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": -32601, "message": "Method not found" },
+            "id": request["id"],
+        }),
+    };
+    let response = response.to_string();
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    );
+}
@@ -0,0 +1,393 @@
+//! Tests of the config manager and password utils against files in a temp dir.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::process::Command;
+
+use serde_json::json;
+
+use common::{lock_env, use_config_dir, FakeServer, TempDir};
+use peach_lib::error::{PeachError, MAINTENANCE_MODE_ERROR_CODE};
+use peach_lib::{config_manager, device_identity, jsonrpc_core, network_client, password_utils};
+
+#[test]
+fn state_round_trips_through_archive() {
+    let dir = TempDir::new("state-round-trip");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::set_device_name("peach-one").unwrap();
+    config_manager::set_admin_email("admin@example.com").unwrap();
+    fs::write(dir.file("htpasswd"), "admin:$2y$hash\n").unwrap();
+    let archive = dir.file("state.tar.gz");
+
+    config_manager::export_state(&archive).unwrap();
+    config_manager::set_device_name("peach-two").unwrap();
+    fs::write(dir.file("htpasswd"), "admin:$2y$other\n").unwrap();
+    let restored = config_manager::import_state(&archive, true).unwrap();
+
+    assert_eq!(restored.device_name, "peach-one");
+    assert_eq!(restored.admin_email, "admin@example.com");
+    assert_eq!(
+        config_manager::load_peach_config().unwrap().device_name,
+        "peach-one"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.file("htpasswd")).unwrap(),
+        "admin:$2y$hash\n"
+    );
+}
+
+#[test]
+fn archive_with_symlinked_member_is_rejected() {
+    let dir = TempDir::new("state-symlink");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::set_device_name("peach-one").unwrap();
+    // an archive whose config.yml points at a file elsewhere on the device
+    let staging = dir.path.join("staging");
+    fs::create_dir(&staging).unwrap();
+    symlink("/etc/passwd", staging.join("config.yml")).unwrap();
+    let archive = dir.file("malicious.tar.gz");
+    let status = Command::new("tar")
+        .args(&[
+            "-czf",
+            &archive,
+            "-C",
+            &staging.to_string_lossy(),
+            "config.yml",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let result = config_manager::import_state(&archive, true);
+
+    assert!(matches!(result, Err(PeachError::StateArchiveError { .. })));
+    assert_eq!(
+        config_manager::load_peach_config().unwrap().device_name,
+        "peach-one"
+    );
+}
+
+#[test]
+fn archive_with_invalid_config_is_rejected() {
+    let dir = TempDir::new("state-invalid");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::set_device_name("peach-one").unwrap();
+    let staging = dir.path.join("staging");
+    fs::create_dir(&staging).unwrap();
+    fs::write(staging.join("config.yml"), "tor_address: not-an-onion\n").unwrap();
+    let archive = dir.file("invalid.tar.gz");
+    let status = Command::new("tar")
+        .args(&[
+            "-czf",
+            &archive,
+            "-C",
+            &staging.to_string_lossy(),
+            "config.yml",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let result = config_manager::import_state(&archive, true);
+
+    assert!(matches!(
+        result,
+        Err(PeachError::InvalidOnionAddress { .. })
+    ));
+    assert_eq!(
+        config_manager::load_peach_config().unwrap().device_name,
+        "peach-one"
+    );
+}
+
+#[test]
+fn maintenance_mode_blocks_network_changes() {
+    let dir = TempDir::new("maintenance-mode");
+    let server = FakeServer::start(&[("reconfigure", json!("success"))]);
+    let _env = lock_env();
+    use_config_dir(&dir);
+    env::set_var("PEACH_NETWORK_SERVER", &server.addr);
+    config_manager::set_maintenance_mode(true).unwrap();
+
+    let err = network_client::reconfigure().unwrap_err();
+    config_manager::set_maintenance_mode(false).unwrap();
+
+    assert!(matches!(err, PeachError::MaintenanceMode));
+    assert!(server.calls().is_empty());
+    assert_eq!(
+        jsonrpc_core::Error::from(err).code,
+        jsonrpc_core::ErrorCode::ServerError(MAINTENANCE_MODE_ERROR_CODE)
+    );
+}
+
+#[test]
+fn maintenance_mode_blocks_enabling_dyndns() {
+    let dir = TempDir::new("maintenance-dyndns");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::set_maintenance_mode(true).unwrap();
+
+    let feature = config_manager::set_feature("dyndns", true);
+    let dyndns_config =
+        config_manager::set_peach_dyndns_config("peach.dyn.peachcloud.org", "", "", true);
+    config_manager::set_maintenance_mode(false).unwrap();
+
+    assert!(matches!(feature, Err(PeachError::MaintenanceMode)));
+    assert!(matches!(dyndns_config, Err(PeachError::MaintenanceMode)));
+    assert!(!config_manager::is_feature_enabled("dyndns").unwrap());
+}
+
+#[test]
+fn webhook_must_be_an_http_url() {
+    let dir = TempDir::new("webhook");
+    let _env = lock_env();
+    use_config_dir(&dir);
+
+    config_manager::set_dyn_update_webhook("https://example.com/hook").unwrap();
+    let file_url = config_manager::set_dyn_update_webhook("file:///etc/shadow");
+    let option = config_manager::set_dyn_update_webhook("--output=/etc/passwd");
+
+    assert!(matches!(
+        file_url,
+        Err(PeachError::InvalidWebhookUrl { .. })
+    ));
+    assert!(matches!(option, Err(PeachError::InvalidWebhookUrl { .. })));
+    assert_eq!(
+        config_manager::get_dyn_update_webhook().unwrap(),
+        Some("https://example.com/hook".to_string())
+    );
+    config_manager::set_dyn_update_webhook("").unwrap();
+    assert_eq!(config_manager::get_dyn_update_webhook().unwrap(), None);
+}
+
+#[test]
+fn reset_token_is_single_use() {
+    let dir = TempDir::new("reset-token");
+    let _env = lock_env();
+    use_config_dir(&dir);
+
+    let token = password_utils::create_reset_token().unwrap();
+
+    assert!(fs::metadata(dir.file("reset_token.json")).is_ok());
+    assert!(matches!(
+        password_utils::consume_reset_token("wrong-token"),
+        Err(PeachError::InvalidResetToken)
+    ));
+    password_utils::verify_reset_token(&token).unwrap();
+    password_utils::consume_reset_token(&token).unwrap();
+    assert!(matches!(
+        password_utils::consume_reset_token(&token),
+        Err(PeachError::InvalidResetToken)
+    ));
+}
+
+#[test]
+fn password_round_trips_through_htpasswd() {
+    if fs::metadata("/usr/bin/htpasswd").is_err() {
+        // htpasswd (from apache2-utils) is only installed on devices
+        return;
+    }
+    let dir = TempDir::new("password");
+    let _env = lock_env();
+    use_config_dir(&dir);
+
+    password_utils::set_new_password("correct horse").unwrap();
+
+    password_utils::verify_password("correct horse").unwrap();
+    assert!(matches!(
+        password_utils::verify_password("battery staple"),
+        Err(PeachError::InvalidPassword)
+    ));
+    assert!(matches!(
+        password_utils::check_password_not_reused("correct horse"),
+        Err(PeachError::PasswordReused)
+    ));
+    assert!(fs::metadata(dir.file("password_history")).is_ok());
+    let mode = fs::metadata(dir.file("htpasswd"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, password_utils::HTPASSWD_FILE_MODE);
+}
+
+#[test]
+fn saving_config_restricts_its_mode() {
+    let dir = TempDir::new("config-mode");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    // a config created before the mode was set on save
+    fs::write(dir.file("config.yml"), "device_name: peach\n").unwrap();
+    fs::set_permissions(dir.file("config.yml"), fs::Permissions::from_mode(0o666)).unwrap();
+
+    config_manager::set_device_name("peach-one").unwrap();
+
+    let mode = fs::metadata(dir.file("config.yml"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, config_manager::CONFIG_FILE_MODE);
+}
+
+#[test]
+fn corrupt_login_attempts_file_is_reset() {
+    let dir = TempDir::new("login-attempts");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    fs::write(dir.file("login_attempts.json"), "{ not json").unwrap();
+
+    // the attempts are checked before the password, so htpasswd is not needed
+    let _ = password_utils::verify_password("correct horse");
+
+    let contents = fs::read_to_string(dir.file("login_attempts.json")).unwrap();
+    assert!(serde_json::from_str::<serde_json::Value>(&contents).is_ok());
+}
+
+#[test]
+fn import_json_validates_the_config() {
+    let dir = TempDir::new("import-json");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::set_device_name("peach-one").unwrap();
+
+    let result = config_manager::import_json(
+        r#"{ "device_name": "peach-two", "tor_address": "example.com" }"#,
+    );
+
+    assert!(matches!(
+        result,
+        Err(PeachError::InvalidOnionAddress { .. })
+    ));
+    assert_eq!(
+        config_manager::load_peach_config().unwrap().device_name,
+        "peach-one"
+    );
+    config_manager::import_json(r#"{ "device_name": "peach-two" }"#).unwrap();
+    assert_eq!(
+        config_manager::load_peach_config().unwrap().device_name,
+        "peach-two"
+    );
+}
+
+#[test]
+fn redacted_export_blanks_the_tsig_key_path() {
+    let dir = TempDir::new("export-json");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    config_manager::import_json(r#"{ "dyn_tsig_key_path": "/etc/peach/tsig.key" }"#).unwrap();
+
+    let full: serde_json::Value =
+        serde_json::from_str(&config_manager::export_json(false).unwrap()).unwrap();
+    let redacted: serde_json::Value =
+        serde_json::from_str(&config_manager::export_json(true).unwrap()).unwrap();
+
+    assert_eq!(full["dyn_tsig_key_path"], "/etc/peach/tsig.key");
+    assert_eq!(redacted["dyn_tsig_key_path"], "");
+}
+
+#[test]
+fn admin_email_must_be_an_address() {
+    let dir = TempDir::new("admin-email");
+    let _env = lock_env();
+    use_config_dir(&dir);
+
+    config_manager::set_admin_email("admin@example.com").unwrap();
+    let injected = config_manager::set_admin_email("admin@example.com\nBcc: all@example.com");
+
+    assert!(matches!(
+        injected,
+        Err(PeachError::InvalidEmailAddress { .. })
+    ));
+    assert_eq!(
+        config_manager::get_admin_email().unwrap(),
+        Some("admin@example.com".to_string())
+    );
+    assert!(matches!(
+        password_utils::sendmail_sender("admin@example.com", "Hi\r\nBcc: all@example.com", "body"),
+        Err(PeachError::SendEmailError { .. })
+    ));
+}
+
+#[test]
+fn peachcloud_domain_falls_back_from_external_to_dyndns() {
+    let dir = TempDir::new("domain-fallback");
+    let _env = lock_env();
+    use_config_dir(&dir);
+
+    assert_eq!(config_manager::get_peachcloud_domain().unwrap(), None);
+    config_manager::set_peach_dyndns_config(
+        "peach.dyn.peachcloud.org",
+        "dynserver.dyn.peachcloud.org",
+        &dir.file("tsig.key"),
+        true,
+    )
+    .unwrap();
+    assert_eq!(
+        config_manager::get_peachcloud_domain().unwrap(),
+        Some("peach.dyn.peachcloud.org".to_string())
+    );
+    config_manager::set_external_domain("peach.example.com").unwrap();
+    assert_eq!(
+        config_manager::get_peachcloud_domain().unwrap(),
+        Some("peach.example.com".to_string())
+    );
+
+    config_manager::clear_external_domain().unwrap();
+    assert_eq!(
+        config_manager::get_peachcloud_domain().unwrap(),
+        Some("peach.dyn.peachcloud.org".to_string())
+    );
+    config_manager::clear_dyndns_config().unwrap();
+    assert_eq!(config_manager::get_peachcloud_domain().unwrap(), None);
+}
+
+#[test]
+fn tor_address_must_be_a_v3_onion_address() {
+    let dir = TempDir::new("tor-address");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    let onion = format!("{}.onion", "a".repeat(56));
+
+    config_manager::set_tor_address(&onion).unwrap();
+    assert_eq!(config_manager::get_tor_address().unwrap(), Some(onion));
+    for invalid in &[".onion", "peach.onion", "example.com"] {
+        assert!(matches!(
+            config_manager::set_tor_address(invalid),
+            Err(PeachError::InvalidOnionAddress { .. })
+        ));
+    }
+    config_manager::set_tor_address("").unwrap();
+    assert_eq!(config_manager::get_tor_address().unwrap(), None);
+}
+
+#[test]
+fn device_identity_prefers_the_same_address_as_the_config() {
+    let dir = TempDir::new("device-identity");
+    let _env = lock_env();
+    use_config_dir(&dir);
+    // keeps sbotcli from being run through sudo
+    env::set_var("PEACH_SBOT_REPO", dir.file("sbot"));
+    let onion = format!("{}.onion", "b".repeat(56));
+    config_manager::set_tor_address(&onion).unwrap();
+    config_manager::set_peach_dyndns_config("peach.dyn.peachcloud.org", "", "", true).unwrap();
+
+    let identity = device_identity::device_identity().unwrap();
+    config_manager::clear_dyndns_config().unwrap();
+    let onion_only = device_identity::device_identity().unwrap();
+    env::remove_var("PEACH_SBOT_REPO");
+
+    assert_eq!(
+        identity.address.as_deref(),
+        Some("peach.dyn.peachcloud.org")
+    );
+    assert_eq!(onion_only.address, Some(onion));
+    assert_eq!(
+        onion_only.address,
+        config_manager::get_peachcloud_address().unwrap()
+    );
+}